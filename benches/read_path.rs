@@ -0,0 +1,77 @@
+//! Compares reading pages with `read_exact` into a buffer against decoding them straight
+//! from a memory map, on a table much larger than the page cache.
+//!
+//! Run with `cargo bench --bench read_path`.
+
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use sqlite_rs::record::Value;
+use sqlite_rs::storage::{MmapVfs, OsVfs, Vfs};
+use sqlite_rs::table::{self, Row, Database};
+
+const ROWS: u32 = 300_000;
+const CACHE_PAGES: usize = 64;
+const SCANS: u32 = 5;
+const LOOKUPS: u32 = 200_000;
+
+fn build(path: &str) {
+    for suffix in ["", "-journal", "-wal"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+    let mut db = Database::open(path.to_string()).unwrap();
+    for id in 0..ROWS {
+        let mut row = Row::new();
+        row.id = id;
+        row.fields = vec![Value::Text("user".to_string()), Value::Text("u@mail.co".to_string())];
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    db.close();
+}
+
+fn open(path: &str, vfs: Rc<dyn Vfs>) -> Database {
+    let mut db = Database::open_with_vfs(path.to_string(), vfs).unwrap();
+    db.pager.set_cache_capacity(CACHE_PAGES).unwrap();
+    db
+}
+
+fn full_scans(path: &str, vfs: Rc<dyn Vfs>) -> Duration {
+    let mut db = open(path, vfs);
+    let start = Instant::now();
+    for _ in 0..SCANS {
+        assert_eq!(table::select_all_rows(&mut db, "users").unwrap().len(), ROWS as usize);
+    }
+    start.elapsed()
+}
+
+fn point_lookups(path: &str, vfs: Rc<dyn Vfs>) -> Duration {
+    let mut db = open(path, vfs);
+    let mut rng = StdRng::seed_from_u64(7);
+    let start = Instant::now();
+    for _ in 0..LOOKUPS {
+        let id = rng.gen_range(0..ROWS);
+        assert!(table::find_row(&mut db, "users", id).unwrap().is_some());
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, read: Duration, mmap: Duration) {
+    println!("{:<14} read_exact {:>9.1?}   mmap {:>9.1?}   speedup {:.2}x",
+        name, read, mmap, read.as_secs_f64() / mmap.as_secs_f64());
+}
+
+fn main() {
+    let path = std::env::temp_dir().join("sqlighter_bench_read_path.db");
+    let path = path.to_str().unwrap();
+    build(path);
+    let size = std::fs::metadata(path).unwrap().len();
+    println!("{} rows, {} pages, cache of {} pages", ROWS, size / 4096, CACHE_PAGES);
+
+    // warm the OS page cache so both paths read from memory
+    full_scans(path, Rc::new(OsVfs));
+    report("full scans", full_scans(path, Rc::new(OsVfs)), full_scans(path, Rc::new(MmapVfs)));
+    report("point lookups", point_lookups(path, Rc::new(OsVfs)), point_lookups(path, Rc::new(MmapVfs)));
+    std::fs::remove_file(path).unwrap();
+}
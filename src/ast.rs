@@ -0,0 +1,120 @@
+use crate::lexer::Position;
+use crate::table::{JournalMode, Order};
+
+/// A name in the statement, with where it was written for error messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String), // a quoted string, or a bare word standing for one
+    Blob(Vec<u8>),
+}
+
+/// A literal, with where it was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constant {
+    pub literal: Literal,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareOp {
+    Equals,
+    NotEquals,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// `column <op> value`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub column: Ident,
+    pub op: CompareOp,
+    pub value: Constant,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub column: Ident,
+    pub order: Order,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insert {
+    pub table: Ident,
+    pub columns: Vec<Ident>, // empty when the values are given for every column, in order
+    pub values: Vec<Constant>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub table: Option<Ident>, // `None` for a bare `select`, which lists the whole table
+    pub filter: Vec<Condition>, // all of them must hold
+    pub order_by: Option<OrderBy>,
+    pub limit: Option<Constant>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delete {
+    pub table: Ident,
+    pub filter: Vec<Condition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub column: Ident,
+    pub value: Constant,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    pub table: Ident,
+    pub assignments: Vec<Assignment>,
+    pub filter: Vec<Condition>,
+}
+
+/// `name type [(size)]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDef {
+    pub name: Ident,
+    pub type_name: Ident,
+    pub size: Option<Constant>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub table: Ident,
+    pub columns: Vec<ColumnDef>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pragma {
+    JournalMode(JournalMode),
+    WalCheckpoint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Insert(Insert),
+    Select(Select),
+    Delete(Delete),
+    Update(Update),
+    CreateTable(CreateTable),
+    Vacuum,
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint(Ident),
+    Release(Ident),
+    RollbackTo(Ident),
+    Pragma(Pragma),
+}
@@ -1,149 +1,726 @@
 use std::error::Error;
 
 use crate::constants;
-use crate::table::{serialize_row, Row};
-use crate::utils::Page;
+use crate::record::{get_varint, put_varint, varint_len};
+use crate::table::Pager;
+use crate::utils::{read_u16, read_u32, write_u16, write_u32, Page};
 
-#[derive(Debug, Clone)]
+/// A row as stored in a leaf. A record longer than `LEAF_NODE_MAX_LOCAL` keeps its first
+/// bytes in the cell and the rest in a chain of overflow pages starting at `overflow_page`.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Cell {
     pub key: u32,
-    pub value: [u8; constants::ROW_SIZE as usize],
+    pub record_size: u32, // bytes of the whole record
+    pub record: Vec<u8>, // the part of the record kept in the leaf
+    pub overflow_page: u32, // 0 when the whole record is in the leaf
 }
 
-impl Cell {
-    pub fn new(row: Row) -> Cell {
-        let mut cell = Cell {
-            key: row.id,
-            value: [0; constants::ROW_SIZE as usize],
-        };
-        serialize_row(&row, cell.value.as_mut());
-        
-        cell
-    }
+/// Pointer to a child page. `key` is the max key stored in that child's subtree.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InternalCell {
+    pub child: u32,
+    pub key: u32,
 }
 
 #[derive(Debug, Clone)]
 pub enum NodeType {
-    NodeInternal(Vec<u32>), // children -> Vec with keys
-    NodeLeaf(Vec<Cell>), // cells 
+    NodeInternal { cells: Vec<InternalCell>, right_child: u32 }, // keys > every cell key live under right_child
+    NodeLeaf { cells: Vec<Cell>, next_leaf: u32 }, // next_leaf is 0 on the rightmost leaf
+    NodeOverflow { data: Vec<u8>, next_page: u32 }, // next_page is 0 at the end of the chain
 }
 
 #[derive(Debug, Clone)]
 pub struct Node {
     pub node_type: NodeType,
     pub parent: u32,
-    pub content_len: u32, // num of cells / children, depending on node type
+    pub content_len: u32, // num of cells / keys / data bytes, depending on node type
 }
 
 pub fn get_content_len(p: Page) -> u32 {
-    let mut content_len = [0u8; 4];
-    content_len.copy_from_slice(&p[constants::NODE_CONTENT_LEN_OFFSET as usize..(constants::NODE_CONTENT_LEN_OFFSET + 4) as usize]);
-    u32::from_ne_bytes(content_len)
+    read_u32(&p, constants::NODE_CONTENT_LEN_OFFSET)
 }
 
 fn set_content_len(p: &mut Page, content_len: u32) {
-    p[constants::NODE_CONTENT_LEN_OFFSET as usize..(constants::NODE_CONTENT_LEN_OFFSET + 4) as usize].copy_from_slice(&content_len.to_ne_bytes());
+    write_u32(p, constants::NODE_CONTENT_LEN_OFFSET, content_len);
+}
+
+fn corrupt_page() -> Box<dyn Error> {
+    Box::new(std::io::Error::other("Malformed page. Corrupt file."))
+}
+
+/// Bytes `cell` takes in a leaf, its cell pointer included.
+pub fn cell_bytes(cell: &Cell) -> u32 {
+    let overflow = if cell.overflow_page != 0 { constants::LEAF_NODE_OVERFLOW_PAGE_SIZE } else { 0 };
+    constants::LEAF_NODE_CELL_POINTER_SIZE + constants::LEAF_NODE_KEY_SIZE + varint_len(cell.record_size as u64) as u32
+        + cell.record.len() as u32 + overflow
+}
+
+/// Bytes `cells` take in a leaf, out of `LEAF_NODE_SPACE`.
+pub fn leaf_bytes(cells: &[Cell]) -> u32 {
+    cells.iter().map(cell_bytes).sum()
 }
 
-fn get_node_type(p: Page) -> Result<NodeType, Box<dyn Error>>{
+fn decode_cell(p: &Page, offset: usize) -> Result<Cell, Box<dyn Error>> {
+    let key_end = offset + constants::LEAF_NODE_KEY_SIZE as usize;
+    if offset < constants::LEAF_NODE_HEADER_SIZE as usize || key_end > p.len() {
+        return Err(corrupt_page());
+    }
+    let key = read_u32(p, offset as u32);
+    let mut i = key_end;
+    let record_size = u32::try_from(get_varint(p, &mut i)?).map_err(|_| corrupt_page())?;
+    let local = record_size.min(constants::LEAF_NODE_MAX_LOCAL) as usize;
+    let record = p.get(i..i + local).ok_or_else(corrupt_page)?.to_vec();
+    i += local;
+    let overflow_page = if local < record_size as usize {
+        if i + constants::LEAF_NODE_OVERFLOW_PAGE_SIZE as usize > p.len() {
+            return Err(corrupt_page());
+        }
+        read_u32(p, i as u32)
+    } else {
+        0
+    };
+    Ok(Cell { key, record_size, record, overflow_page })
+}
+
+fn get_node_type(p: &Page) -> Result<NodeType, Box<dyn Error>>{
     match p[0] {
         0 => {
-            let mut children = Vec::new();
-            for i in 0..get_content_len(p) {
-                let mut child = [0u8; 4];
-                child.copy_from_slice(&p[(constants::NODE_CONTENT_OFFSET + i * 4) as usize..(constants::NODE_CONTENT_OFFSET + i * 4 + 4) as usize]);
-                children.push(u32::from_ne_bytes(child));
+            let right_child = read_u32(p, constants::INTERNAL_NODE_RIGHT_CHILD_OFFSET);
+
+            let mut cells = Vec::new();
+            let mut i = constants::INTERNAL_NODE_HEADER_SIZE;
+            for _ in 0..read_u32(p, constants::NODE_CONTENT_LEN_OFFSET) {
+                let child = read_u32(p, i);
+                i += constants::INTERNAL_NODE_CHILD_SIZE;
+                let key = read_u32(p, i);
+                i += constants::INTERNAL_NODE_KEY_SIZE;
+                cells.push(InternalCell { child, key });
             }
-            Ok(NodeType::NodeInternal(children))
+            Ok(NodeType::NodeInternal { cells, right_child })
         },
         1 => {
+            let next_leaf = read_u32(p, constants::LEAF_NODE_NEXT_LEAF_OFFSET);
+
+            let content_len = read_u32(p, constants::NODE_CONTENT_LEN_OFFSET);
+            if content_len > constants::LEAF_NODE_SPACE / constants::LEAF_NODE_CELL_POINTER_SIZE {
+                return Err(corrupt_page());
+            }
             let mut cells = Vec::new();
-            let mut i = constants::NODE_CONTENT_OFFSET;
-            let content_len = get_content_len(p);
-            for _ in 0..content_len {
-                let mut key = [0u8; 4];
-                key.copy_from_slice(&p[i as usize..(i + 4) as usize]);
-                let key = u32::from_ne_bytes(key);
-                i += 4;
-                let mut value = [0u8; constants::ROW_SIZE as usize];
-                value.copy_from_slice(&p[i as usize..(i + constants::ROW_SIZE) as usize]);
-                i += constants::ROW_SIZE;
-                cells.push(Cell { key, value });
+            for cell_num in 0..content_len {
+                let pointer = constants::LEAF_NODE_HEADER_SIZE + cell_num * constants::LEAF_NODE_CELL_POINTER_SIZE;
+                cells.push(decode_cell(p, read_u16(p, pointer) as usize)?);
+            }
+            Ok(NodeType::NodeLeaf { cells, next_leaf })
+        },
+        2 => {
+            let next_page = read_u32(p, constants::OVERFLOW_NEXT_PAGE_OFFSET);
+            let len = read_u32(p, constants::OVERFLOW_LEN_OFFSET);
+            if len > constants::OVERFLOW_PAGE_CAPACITY {
+                return Err(corrupt_page());
             }
-            Ok(NodeType::NodeLeaf(cells))
+            let start = constants::OVERFLOW_HEADER_SIZE as usize;
+            Ok(NodeType::NodeOverflow { data: p[start..start + len as usize].to_vec(), next_page })
         },
         _ => Err("Invalid node type".into()),
     }
 }
 
-fn get_parent(p: Page) -> u32 {
-    let mut parent = [0u8; 4];
-    parent.copy_from_slice(&p[constants::NODE_PARENT_OFFSET as usize..(constants::NODE_PARENT_OFFSET + 4) as usize]);
-    u32::from_ne_bytes(parent)
+fn get_parent(p: &Page) -> u32 {
+    read_u32(p, constants::NODE_PARENT_OFFSET)
 }
 
-// fn set_parent(p: &mut Page, parent: u32) {
-//     p[constants::NODE_PARENT_OFFSET as usize..(constants::NODE_PARENT_OFFSET + 4) as usize].copy_from_slice(&parent.to_ne_bytes());
-// }
+fn set_parent(p: &mut Page, parent: u32) {
+    write_u32(p, constants::NODE_PARENT_OFFSET, parent);
+}
 
 pub fn get_node(p: Page) -> Result<Node, Box<dyn Error>> {
+    decode_node(&p)
+}
+
+/// Same as `get_node`, without taking a copy of the page: it can be decoded where it lies,
+/// in a memory-mapped file for instance.
+pub fn decode_node(p: &Page) -> Result<Node, Box<dyn Error>> {
     let node_type = get_node_type(p)?;
+    if let NodeType::NodeOverflow { data, .. } = &node_type {
+        return Ok(Node { parent: 0, content_len: data.len() as u32, node_type });
+    }
     Ok(Node {
         node_type,
         parent: get_parent(p),
-        content_len: get_content_len(p),
+        content_len: read_u32(p, constants::NODE_CONTENT_LEN_OFFSET),
     })
 }
 
 impl Node {
     pub fn to_page(self) -> Page {
         let mut page = [0; constants::PAGE_SIZE as usize];
+        set_parent(&mut page, self.parent);
         match self.node_type {
-            NodeType::NodeInternal(children) => {
+            NodeType::NodeInternal { cells, right_child } => {
                 page[0] = 0;
-                set_content_len(&mut page, children.len() as u32);
-                for (i, child) in children.iter().enumerate() {
-                    page[(constants::NODE_CONTENT_OFFSET as usize + i * 4) as usize..(constants::NODE_CONTENT_OFFSET as usize + i * 4 + 4) as usize].copy_from_slice(&child.to_ne_bytes());
+                set_content_len(&mut page, cells.len() as u32);
+                write_u32(&mut page, constants::INTERNAL_NODE_RIGHT_CHILD_OFFSET, right_child);
+                let mut i = constants::INTERNAL_NODE_HEADER_SIZE;
+                for cell in cells {
+                    write_u32(&mut page, i, cell.child);
+                    i += constants::INTERNAL_NODE_CHILD_SIZE;
+                    write_u32(&mut page, i, cell.key);
+                    i += constants::INTERNAL_NODE_KEY_SIZE;
                 }
             },
-            NodeType::NodeLeaf(cells) => {
+            NodeType::NodeLeaf { cells, next_leaf } => {
                 page[0] = 1;
                 set_content_len(&mut page, cells.len() as u32);
-                let mut i = constants::NODE_CONTENT_OFFSET;
-                for cell in cells {
-                    page[i as usize..(i + 4) as usize].copy_from_slice(&cell.key.to_ne_bytes());
-                    i += 4;
-                    page[i as usize..(i + constants::ROW_SIZE) as usize].copy_from_slice(&cell.value);
-                    i += constants::ROW_SIZE;
+                write_u32(&mut page, constants::LEAF_NODE_NEXT_LEAF_OFFSET, next_leaf);
+                // cells are packed from the end of the page, in key order
+                let mut offset = constants::PAGE_SIZE;
+                for (cell_num, cell) in cells.iter().enumerate() {
+                    offset -= cell_bytes(cell) - constants::LEAF_NODE_CELL_POINTER_SIZE;
+                    let pointer = constants::LEAF_NODE_HEADER_SIZE + cell_num as u32 * constants::LEAF_NODE_CELL_POINTER_SIZE;
+                    write_u16(&mut page, pointer, offset as u16);
+
+                    let mut bytes = cell.key.to_le_bytes().to_vec();
+                    put_varint(&mut bytes, cell.record_size as u64);
+                    bytes.extend_from_slice(&cell.record);
+                    if cell.overflow_page != 0 {
+                        bytes.extend_from_slice(&cell.overflow_page.to_le_bytes());
+                    }
+                    page[offset as usize..offset as usize + bytes.len()].copy_from_slice(&bytes);
                 }
             },
+            NodeType::NodeOverflow { data, next_page } => {
+                page[0] = 2;
+                write_u32(&mut page, constants::OVERFLOW_NEXT_PAGE_OFFSET, next_page);
+                write_u32(&mut page, constants::OVERFLOW_LEN_OFFSET, data.len() as u32);
+                let start = constants::OVERFLOW_HEADER_SIZE as usize;
+                page[start..start + data.len()].copy_from_slice(&data);
+            },
         }
         page
     }
+}
 
-    pub fn insert_cell(&mut self, cell: Cell) -> Result<(), Box<dyn Error>> {
 
-        match &mut self.node_type {
-            NodeType::NodeInternal(children) => {
-                children.push(cell.key);
-                children.sort();
+pub fn new_leaf() -> Node {
+    Node {
+        node_type: NodeType::NodeLeaf { cells: Vec::new(), next_leaf: 0 },
+        parent: 0,
+        content_len: 0,
+    }
+}
+
+fn leaf_with_cells(cells: Vec<Cell>, next_leaf: u32, parent: u32) -> Node {
+    Node {
+        content_len: cells.len() as u32,
+        node_type: NodeType::NodeLeaf { cells, next_leaf },
+        parent,
+    }
+}
+
+fn internal_with_cells(cells: Vec<InternalCell>, right_child: u32, parent: u32) -> Node {
+    Node {
+        content_len: cells.len() as u32,
+        node_type: NodeType::NodeInternal { cells, right_child },
+        parent,
+    }
+}
+
+/// Page number of the child whose subtree should contain `key`.
+pub fn internal_node_find_child(cells: &[InternalCell], right_child: u32, key: u32) -> u32 {
+    let mut left = 0;
+    let mut right = cells.len();
+    while left < right {
+        let mid = (left + right) / 2;
+        if cells[mid].key >= key {
+            right = mid;
+        } else {
+            left = mid + 1;
+        }
+    }
+
+    if left == cells.len() {
+        right_child
+    } else {
+        cells[left].child
+    }
+}
+
+/// The cell storing `record` under `key`. The part of the record that does not fit in the
+/// leaf is written to new overflow pages.
+pub fn new_cell(pager: &mut Pager, key: u32, mut record: Vec<u8>) -> Result<Cell, Box<dyn Error>> {
+    let record_size = match u32::try_from(record.len()) {
+        Ok(record_size) => record_size,
+        Err(_) => return Err(Box::new(std::io::Error::other(format!("Row {} is too large to be stored", key)))),
+    };
+    if record_size <= constants::LEAF_NODE_MAX_LOCAL {
+        return Ok(Cell { key, record_size, record, overflow_page: 0 });
+    }
+
+    // the chain is written from its end, so every page knows the next one
+    let rest = record.split_off(constants::LEAF_NODE_MAX_LOCAL as usize);
+    let mut next_page = 0;
+    for data in rest.chunks(constants::OVERFLOW_PAGE_CAPACITY as usize).rev() {
+        next_page = pager.insert_page(Node {
+            content_len: data.len() as u32,
+            node_type: NodeType::NodeOverflow { data: data.to_vec(), next_page },
+            parent: 0,
+        })?;
+    }
+    Ok(Cell { key, record_size, record, overflow_page: next_page })
+}
+
+/// Page numbers of the overflow chain of `cell`, checking it holds the whole record.
+fn overflow_pages(pager: &mut Pager, cell: &Cell) -> Result<Vec<u32>, Box<dyn Error>> {
+    let mut page_nums = Vec::new();
+    let mut len = cell.record.len();
+    let mut page_num = cell.overflow_page;
+    while page_num != 0 {
+        match &pager.get_page(page_num)?.node_type {
+            NodeType::NodeOverflow { data, next_page } if len < cell.record_size as usize => {
+                len += data.len();
+                page_nums.push(page_num);
+                page_num = *next_page;
             },
-            NodeType::NodeLeaf(cells) => {
-                cells.push(cell);
-                cells.sort_by(|a, b| a.key.cmp(&b.key));
+            _ => return Err(corrupt_page()),
+        }
+    }
+    if len != cell.record_size as usize {
+        return Err(corrupt_page());
+    }
+    Ok(page_nums)
+}
+
+/// The whole record of `cell`, read back from its overflow pages if it has any.
+pub fn cell_record(pager: &mut Pager, cell: &Cell) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut record = cell.record.clone();
+    for page_num in overflow_pages(pager, cell)? {
+        if let NodeType::NodeOverflow { data, .. } = &pager.get_page(page_num)?.node_type {
+            record.extend_from_slice(data);
+        }
+    }
+    Ok(record)
+}
+
+/// Frees the overflow pages of a cell removed from its leaf.
+pub fn free_overflow(pager: &mut Pager, cell: &Cell) -> Result<(), Box<dyn Error>> {
+    for page_num in overflow_pages(pager, cell)? {
+        pager.free_page(page_num)?;
+    }
+    Ok(())
+}
+
+fn leaf_cells_mut(node: &mut Node) -> Result<&mut Vec<Cell>, Box<dyn Error>> {
+    match &mut node.node_type {
+        NodeType::NodeLeaf { cells, .. } => Ok(cells),
+        _ => Err(Box::new(std::io::Error::other("Rows are only stored in leaf nodes"))),
+    }
+}
+
+/// Inserts `cell` at position `cell_num` of the leaf, splitting the leaf once its cells
+/// no longer fit in the page.
+pub fn leaf_node_insert(pager: &mut Pager, root_page_num: u32, page_num: u32, cell_num: u32, cell: Cell) -> Result<(), Box<dyn Error>> {
+    let node = pager.get_page_mut(page_num)?;
+    let cells = leaf_cells_mut(node)?;
+    cells.insert(cell_num as usize, cell);
+    let bytes = leaf_bytes(cells);
+    node.content_len += 1;
+
+    if bytes > constants::LEAF_NODE_SPACE {
+        leaf_node_split(pager, root_page_num, page_num)?;
+    }
+    Ok(())
+}
+
+/// Replaces the cell at `cell_num` by `cell`, with the same key. The old cell's overflow
+/// pages are freed, and the leaf is split or rebalanced if its size changed too much.
+pub fn leaf_node_update(pager: &mut Pager, root_page_num: u32, page_num: u32, cell_num: u32, cell: Cell) -> Result<(), Box<dyn Error>> {
+    let cells = leaf_cells_mut(pager.get_page_mut(page_num)?)?;
+    let old = match cells.get_mut(cell_num as usize) {
+        Some(old) if old.key == cell.key => std::mem::replace(old, cell),
+        _ => return Err(Box::new(std::io::Error::other("No row with this id at cursor position"))),
+    };
+    let bytes = leaf_bytes(cells);
+    free_overflow(pager, &old)?;
+
+    if bytes > constants::LEAF_NODE_SPACE {
+        leaf_node_split(pager, root_page_num, page_num)?;
+    } else if bytes < constants::LEAF_NODE_MIN_BYTES && page_num != root_page_num {
+        rebalance(pager, root_page_num, page_num)?;
+    }
+    Ok(())
+}
+
+/// Index cutting `cells` in two non-empty runs whose sizes are as close as possible.
+fn balanced_split(cells: &[Cell]) -> usize {
+    let total = leaf_bytes(cells);
+    let mut left = 0;
+    let mut best = (u32::MAX, 1);
+    for (i, cell) in cells[..cells.len() - 1].iter().enumerate() {
+        left += cell_bytes(cell);
+        best = best.min((left.abs_diff(total - left), i + 1));
+    }
+    best.1
+}
+
+/// Splits the overfull leaf at `page_num` in two halves of about the same size.
+fn leaf_node_split(pager: &mut Pager, root_page_num: u32, page_num: u32) -> Result<(), Box<dyn Error>> {
+    let old_node = pager.get_page(page_num)?.clone();
+    let (mut cells, next_leaf) = match old_node.node_type {
+        NodeType::NodeLeaf { cells, next_leaf } => (cells, next_leaf),
+        _ => return Err(Box::new(std::io::Error::other("Tried to split an internal node as a leaf"))),
+    };
+
+    let right_cells = cells.split_off(balanced_split(&cells));
+    let separator = cells[cells.len() - 1].key;
+
+    // split_and_promote links the left half to the right one once it has a page
+    let left = leaf_with_cells(cells, 0, old_node.parent);
+    let right = leaf_with_cells(right_cells, next_leaf, old_node.parent);
+    split_and_promote(pager, root_page_num, page_num, left, right, separator)
+}
+
+fn internal_node_insert(pager: &mut Pager, root_page_num: u32, page_num: u32, child_page_num: u32, separator: u32, new_child_page_num: u32) -> Result<(), Box<dyn Error>> {
+    let node = pager.get_page(page_num)?.clone();
+    let (mut cells, mut right_child) = match node.node_type {
+        NodeType::NodeInternal { cells, right_child } => (cells, right_child),
+        _ => return Err(Box::new(std::io::Error::other("Parent of a node must be an internal node"))),
+    };
+
+    // the old child keeps the lower half: its key becomes the separator and the
+    // new child takes over the old key (or the right child slot)
+    if right_child == child_page_num {
+        cells.push(InternalCell { child: child_page_num, key: separator });
+        right_child = new_child_page_num;
+    } else {
+        let pos = match cells.iter().position(|c| c.child == child_page_num) {
+            Some(pos) => pos,
+            None => return Err(Box::new(std::io::Error::other("Child not found in parent node"))),
+        };
+        let old_key = cells[pos].key;
+        cells[pos].key = separator;
+        cells.insert(pos + 1, InternalCell { child: new_child_page_num, key: old_key });
+    }
+
+    if cells.len() <= constants::INTERNAL_NODE_MAX_CELLS as usize {
+        *pager.get_page_mut(page_num)? = internal_with_cells(cells, right_child, node.parent);
+        return Ok(());
+    }
+
+    // split: the middle cell moves up to the parent and its child becomes the left right_child
+    let mut right_cells = cells.split_off(cells.len() / 2);
+    let middle = right_cells.remove(0);
+    let left = internal_with_cells(cells, middle.child, node.parent);
+    let right = internal_with_cells(right_cells, right_child, node.parent);
+    split_and_promote(pager, root_page_num, page_num, left, right, middle.key)
+}
+
+/// Replaces the overflowing node at `page_num` by `left` and `right`, linking both into the parent.
+/// The root always stays on `root_page_num`: when it splits, both halves move to new pages
+/// and the root becomes an internal node pointing to them.
+fn split_and_promote(pager: &mut Pager, root_page_num: u32, page_num: u32, mut left: Node, mut right: Node, separator: u32) -> Result<(), Box<dyn Error>> {
+    if page_num == root_page_num {
+        left.parent = root_page_num;
+        right.parent = root_page_num;
+        let right_page_num = pager.insert_page(right)?;
+        set_next_leaf(&mut left, right_page_num);
+        let left_page_num = pager.insert_page(left)?;
+        update_children_parent(pager, left_page_num)?;
+        update_children_parent(pager, right_page_num)?;
+
+        let root = pager.get_page_mut(root_page_num)?;
+        let parent = root.parent;
+        *root = internal_with_cells(vec![InternalCell { child: left_page_num, key: separator }], right_page_num, parent);
+        return Ok(());
+    }
+
+    let parent_page_num = left.parent;
+    right.parent = parent_page_num;
+    let right_page_num = pager.insert_page(right)?;
+    set_next_leaf(&mut left, right_page_num);
+    *pager.get_page_mut(page_num)? = left;
+    update_children_parent(pager, right_page_num)?;
+
+    internal_node_insert(pager, root_page_num, parent_page_num, page_num, separator, right_page_num)
+}
+
+fn set_next_leaf(node: &mut Node, page_num: u32) {
+    if let NodeType::NodeLeaf { next_leaf, .. } = &mut node.node_type {
+        *next_leaf = page_num;
+    }
+}
+
+fn update_children_parent(pager: &mut Pager, page_num: u32) -> Result<(), Box<dyn Error>> {
+    let children = match &pager.get_page(page_num)?.node_type {
+        NodeType::NodeInternal { cells, right_child } => cells.iter().map(|c| c.child).chain([*right_child]).collect::<Vec<u32>>(),
+        _ => return Ok(()),
+    };
+    for child in children {
+        pager.get_page_mut(child)?.parent = page_num;
+    }
+    Ok(())
+}
+
+fn internal_children(node: &Node) -> Result<(Vec<u32>, Vec<u32>), Box<dyn Error>> {
+    match &node.node_type {
+        NodeType::NodeInternal { cells, right_child } => Ok((
+            cells.iter().map(|c| c.child).chain([*right_child]).collect(),
+            cells.iter().map(|c| c.key).collect(),
+        )),
+        _ => Err(Box::new(std::io::Error::other("Parent of a node must be an internal node"))),
+    }
+}
+
+fn internal_from_children(mut children: Vec<u32>, keys: Vec<u32>, parent: u32) -> Node {
+    let right_child = children.pop().unwrap();
+    let cells = children.into_iter().zip(keys).map(|(child, key)| InternalCell { child, key }).collect();
+    internal_with_cells(cells, right_child, parent)
+}
+
+pub fn leaf_node_delete(pager: &mut Pager, root_page_num: u32, page_num: u32, cell_num: u32) -> Result<(), Box<dyn Error>> {
+    let node = pager.get_page_mut(page_num)?;
+    let cells = match &mut node.node_type {
+        NodeType::NodeLeaf { cells, .. } => cells,
+        _ => return Err(Box::new(std::io::Error::other("Cannot delete a row from an internal node"))),
+    };
+    if cell_num as usize >= cells.len() {
+        return Err(Box::new(std::io::Error::other("No row to delete at cursor position")));
+    }
+
+    let removed = cells.remove(cell_num as usize);
+    let num_cells = cells.len() as u32;
+    let bytes = leaf_bytes(cells);
+    let new_max = cells.last().map(|c| c.key);
+    node.content_len = num_cells;
+    free_overflow(pager, &removed)?;
+
+    if page_num == root_page_num {
+        return Ok(());
+    }
+    if cell_num == num_cells {
+        if let Some(new_max) = new_max {
+            update_max_key(pager, root_page_num, page_num, new_max)?;
+        }
+    }
+    if bytes < constants::LEAF_NODE_MIN_BYTES {
+        rebalance(pager, root_page_num, page_num)?;
+    }
+    Ok(())
+}
+
+/// Keeps the separator keys exact after the max key of `page_num`'s subtree changed.
+fn update_max_key(pager: &mut Pager, root_page_num: u32, page_num: u32, new_max: u32) -> Result<(), Box<dyn Error>> {
+    let mut child_page_num = page_num;
+    while child_page_num != root_page_num {
+        let parent_page_num = pager.get_page(child_page_num)?.parent;
+        match &mut pager.get_page_mut(parent_page_num)?.node_type {
+            NodeType::NodeInternal { cells, right_child } => {
+                if *right_child != child_page_num {
+                    if let Some(cell) = cells.iter_mut().find(|c| c.child == child_page_num) {
+                        cell.key = new_max;
+                    }
+                    return Ok(());
+                }
             },
+            _ => return Err(Box::new(std::io::Error::other("Parent of a node must be an internal node"))),
         }
-        self.content_len += 1;
-        Ok(())
+        // the right child's max is also the parent's max: keep going up
+        child_page_num = parent_page_num;
     }
+    Ok(())
+}
+
+/// Fixes an underflowing non-root node by merging it with a sibling or, when both do not fit in
+/// one node, by moving cells over from the sibling: leaves share their bytes evenly, internal
+/// nodes rotate a single child.
+/// Merges can make the parent underflow in turn; an internal root left with a single child
+/// is collapsed into that child.
+fn rebalance(pager: &mut Pager, root_page_num: u32, page_num: u32) -> Result<(), Box<dyn Error>> {
+    let parent_page_num = pager.get_page(page_num)?.parent;
+    let parent = pager.get_page(parent_page_num)?.clone();
+    let (mut children, mut keys) = internal_children(&parent)?;
+    let idx = match children.iter().position(|&c| c == page_num) {
+        Some(idx) => idx,
+        None => return Err(Box::new(std::io::Error::other("Child not found in parent node"))),
+    };
 
+    // always work on a (left, right) pair of adjacent siblings
+    let left_idx = if idx > 0 { idx - 1 } else { idx };
+    let left_page_num = children[left_idx];
+    let right_page_num = children[left_idx + 1];
+    let left = pager.get_page(left_page_num)?.clone();
+    let right = pager.get_page(right_page_num)?.clone();
+
+    // the leaf now ending where the right sibling ended, with its real max key: the key
+    // kept for the right sibling is stale when its last cell was deleted
+    let mut right_max = None;
+    let merged = match (left.node_type, right.node_type) {
+        (NodeType::NodeLeaf { cells: mut left_cells, next_leaf: left_next_leaf },
+         NodeType::NodeLeaf { cells: mut right_cells, next_leaf: right_next_leaf }) => {
+            if leaf_bytes(&left_cells) + leaf_bytes(&right_cells) <= constants::LEAF_NODE_SPACE {
+                left_cells.append(&mut right_cells);
+                right_max = left_cells.last().map(|c| (left_page_num, c.key));
+                *pager.get_page_mut(left_page_num)? = leaf_with_cells(left_cells, right_next_leaf, parent_page_num);
+                true
+            } else {
+                left_cells.append(&mut right_cells);
+                let right_cells = left_cells.split_off(balanced_split(&left_cells));
+                keys[left_idx] = left_cells[left_cells.len() - 1].key;
+                right_max = right_cells.last().map(|c| (right_page_num, c.key));
+                *pager.get_page_mut(left_page_num)? = leaf_with_cells(left_cells, left_next_leaf, parent_page_num);
+                *pager.get_page_mut(right_page_num)? = leaf_with_cells(right_cells, right_next_leaf, parent_page_num);
+                false
+            }
+        },
+        (NodeType::NodeInternal { cells: mut left_cells, right_child: left_right_child },
+         NodeType::NodeInternal { cells: mut right_cells, right_child: right_right_child }) => {
+            let separator = keys[left_idx];
+            if left_cells.len() + right_cells.len() < constants::INTERNAL_NODE_MAX_CELLS as usize {
+                left_cells.push(InternalCell { child: left_right_child, key: separator });
+                left_cells.append(&mut right_cells);
+                *pager.get_page_mut(left_page_num)? = internal_with_cells(left_cells, right_right_child, parent_page_num);
+                update_children_parent(pager, left_page_num)?;
+                true
+            } else {
+                // rotate one child through the separator key in the parent
+                let (left_right_child, moved_child, moved_to) = if idx > 0 {
+                    let last = left_cells.pop().unwrap();
+                    right_cells.insert(0, InternalCell { child: left_right_child, key: separator });
+                    keys[left_idx] = last.key;
+                    (last.child, left_right_child, right_page_num)
+                } else {
+                    let first = right_cells.remove(0);
+                    left_cells.push(InternalCell { child: left_right_child, key: separator });
+                    keys[left_idx] = first.key;
+                    (first.child, first.child, left_page_num)
+                };
+                *pager.get_page_mut(left_page_num)? = internal_with_cells(left_cells, left_right_child, parent_page_num);
+                *pager.get_page_mut(right_page_num)? = internal_with_cells(right_cells, right_right_child, parent_page_num);
+                pager.get_page_mut(moved_child)?.parent = moved_to;
+                false
+            }
+        },
+        _ => return Err(Box::new(std::io::Error::other("Siblings must have the same node type"))),
+    };
+
+    if merged {
+        // the merged node takes over the right sibling's key
+        keys.remove(left_idx);
+        children.remove(left_idx + 1);
+        pager.free_page(right_page_num)?;
+    }
+
+    if parent_page_num == root_page_num && keys.is_empty() {
+        // root collapse: the only child moves into the root page
+        let mut child = pager.get_page(children[0])?.clone();
+        child.parent = parent.parent;
+        *pager.get_page_mut(root_page_num)? = child;
+        update_children_parent(pager, root_page_num)?;
+        pager.free_page(children[0])?;
+        return Ok(());
+    }
+
+    let num_keys = keys.len() as u32;
+    *pager.get_page_mut(parent_page_num)? = internal_from_children(children, keys, parent.parent);
+    if let Some((page_num, max_key)) = right_max {
+        update_max_key(pager, root_page_num, page_num, max_key)?;
+    }
+    if parent_page_num != root_page_num && num_keys < constants::INTERNAL_NODE_MIN_CELLS {
+        rebalance(pager, root_page_num, parent_page_num)?;
+    }
+    Ok(())
 }
 
+/// Cuts `items` in chunks of at most `max`, merging and halving the last two chunks when the
+/// last one would have less than `min` items.
+fn split_evenly<T>(items: Vec<T>, max: usize, min: usize) -> Vec<Vec<T>> {
+    let mut items = items.into_iter().peekable();
+    let mut chunks = vec![items.by_ref().take(max).collect::<Vec<T>>()];
+    while items.peek().is_some() {
+        chunks.push(items.by_ref().take(max).collect());
+    }
+    if chunks.len() > 1 && chunks[chunks.len() - 1].len() < min {
+        let mut last = chunks.pop().unwrap();
+        let mut previous = chunks.pop().unwrap();
+        previous.append(&mut last);
+        let half = previous.len() / 2;
+        chunks.push(previous.drain(..half).collect());
+        chunks.push(previous);
+    }
+    chunks
+}
 
-pub fn new_leaf() -> Node {
-    Node {
-        node_type: NodeType::NodeLeaf(Vec::new()),
-        parent: 0,
-        content_len: 0,
+/// Builds a tree from `rows`, (key, record) pairs sorted by key, with every node packed as full
+/// as possible, one level at a time from the leaves up. The top level is written on `root_page_num`.
+/// A leaf is written as soon as it is full, so the rows are only read one at a time.
+pub fn bulk_load<I>(pager: &mut Pager, root_page_num: u32, rows: I) -> Result<(), Box<dyn Error>>
+where
+    I: IntoIterator<Item = Result<(u32, Vec<u8>), Box<dyn Error>>>,
+{
+    // (page number, max key) of every node of the level being built
+    let mut level = Vec::new();
+    let mut cells: Vec<Cell> = Vec::new();
+    let mut bytes = 0;
+    // page of the leaf being filled, taken once a second leaf is needed
+    let mut page_num = None;
+    for row in rows {
+        let (key, record) = row?;
+        let cell = new_cell(pager, key, record)?;
+        if bytes + cell_bytes(&cell) > constants::LEAF_NODE_SPACE {
+            let full_page_num = match page_num {
+                Some(page_num) => page_num,
+                None => pager.insert_page(new_leaf())?,
+            };
+            let next_leaf = pager.insert_page(new_leaf())?;
+            level.push((full_page_num, cells[cells.len() - 1].key));
+            *pager.get_page_mut(full_page_num)? = leaf_with_cells(std::mem::take(&mut cells), next_leaf, 0);
+            page_num = Some(next_leaf);
+            bytes = 0;
+        }
+        bytes += cell_bytes(&cell);
+        cells.push(cell);
+    }
+
+    let page_num = match page_num {
+        Some(page_num) => page_num,
+        None => {
+            let root = pager.get_page_mut(root_page_num)?;
+            let parent = root.parent;
+            *root = leaf_with_cells(cells, 0, parent);
+            return Ok(());
+        },
+    };
+    if bytes < constants::LEAF_NODE_MIN_BYTES {
+        // the last leaf shares the rows of the full one before it
+        let (previous_page_num, _) = level.pop().unwrap();
+        let previous = pager.get_page_mut(previous_page_num)?;
+        let mut previous_cells = std::mem::take(leaf_cells_mut(previous)?);
+        previous_cells.append(&mut cells);
+        cells = previous_cells.split_off(balanced_split(&previous_cells));
+        level.push((previous_page_num, previous_cells[previous_cells.len() - 1].key));
+        *previous = leaf_with_cells(previous_cells, page_num, 0);
+    }
+    level.push((page_num, cells[cells.len() - 1].key));
+    *pager.get_page_mut(page_num)? = leaf_with_cells(cells, 0, 0);
+
+    loop {
+        let mut groups = split_evenly(level, constants::INTERNAL_NODE_MAX_CELLS as usize + 1, constants::INTERNAL_NODE_MIN_CELLS as usize + 1);
+        let top = groups.len() == 1;
+        level = Vec::new();
+        for mut children in groups.drain(..) {
+            let (right_child, max_key) = children.pop().unwrap();
+            let cells = children.into_iter().map(|(child, key)| InternalCell { child, key }).collect();
+            let page_num = if top {
+                root_page_num
+            } else {
+                pager.insert_page(new_leaf())?
+            };
+            let parent = if top { pager.get_page(root_page_num)?.parent } else { 0 };
+            *pager.get_page_mut(page_num)? = internal_with_cells(cells, right_child, parent);
+            update_children_parent(pager, page_num)?;
+            level.push((page_num, max_key));
+        }
+        if top {
+            return Ok(());
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::btree::Node;
+
+pub struct CachedPage {
+    pub node: Node,
+    pub dirty: bool, // modified since it was last written to the file
+    last_used: u64,
+}
+
+/// Bounded set of decoded pages with least-recently-used eviction. Pinned pages are never
+/// evicted, so the cache may grow past its capacity while every page in it is pinned.
+pub struct PageCache {
+    capacity: usize,
+    pages: HashMap<u32, CachedPage>,
+    lru: BTreeMap<u64, u32>, // last use -> page number, oldest first
+    pins: HashMap<u32, u32>, // page number -> pin count
+    clock: u64,
+}
+
+impl PageCache {
+    pub fn new(capacity: usize) -> PageCache {
+        PageCache {
+            capacity: capacity.max(1),
+            pages: HashMap::new(),
+            lru: BTreeMap::new(),
+            pins: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub fn contains(&self, page_num: u32) -> bool {
+        self.pages.contains_key(&page_num)
+    }
+
+    fn touch(&mut self, page_num: u32) {
+        if let Some(page) = self.pages.get_mut(&page_num) {
+            self.lru.remove(&page.last_used);
+            self.clock += 1;
+            page.last_used = self.clock;
+            self.lru.insert(self.clock, page_num);
+        }
+    }
+
+    pub fn get(&mut self, page_num: u32) -> Option<&Node> {
+        self.touch(page_num);
+        self.pages.get(&page_num).map(|page| &page.node)
+    }
+
+    /// Same as `get`, but flags the page as dirty.
+    pub fn get_mut(&mut self, page_num: u32) -> Option<&mut Node> {
+        self.touch(page_num);
+        self.pages.get_mut(&page_num).map(|page| {
+            page.dirty = true;
+            &mut page.node
+        })
+    }
+
+    pub fn insert(&mut self, page_num: u32, node: Node, dirty: bool) {
+        self.remove(page_num);
+        self.clock += 1;
+        self.pages.insert(page_num, CachedPage { node, dirty, last_used: self.clock });
+        self.lru.insert(self.clock, page_num);
+    }
+
+    pub fn remove(&mut self, page_num: u32) -> Option<CachedPage> {
+        let page = self.pages.remove(&page_num)?;
+        self.lru.remove(&page.last_used);
+        Some(page)
+    }
+
+    /// Takes out the least recently used unpinned page if the cache holds more than `keep`
+    /// pages. Dirty victims must be written back by the caller.
+    pub fn evict(&mut self, keep: usize) -> Option<(u32, CachedPage)> {
+        if self.pages.len() <= keep {
+            return None;
+        }
+        let page_num = self.lru.values().copied().find(|page_num| !self.pins.contains_key(page_num))?;
+        self.remove(page_num).map(|page| (page_num, page))
+    }
+
+    pub fn pin(&mut self, page_num: u32) {
+        *self.pins.entry(page_num).or_insert(0) += 1;
+    }
+
+    pub fn unpin(&mut self, page_num: u32) {
+        if let Some(count) = self.pins.get_mut(&page_num) {
+            *count -= 1;
+            if *count == 0 {
+                self.pins.remove(&page_num);
+            }
+        }
+    }
+
+    pub fn is_pinned(&self, page_num: u32) -> bool {
+        self.pins.contains_key(&page_num)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &CachedPage)> {
+        self.pages.iter().map(|(page_num, page)| (*page_num, page))
+    }
+
+    /// Drops every page. Pins are kept.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.lru.clear();
+    }
+
+    pub fn mark_clean(&mut self) {
+        for page in self.pages.values_mut() {
+            page.dirty = false;
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::error::Error;
+
+use crate::constants;
+use crate::cursor::{cursor_advance, cursor_insert, cursor_row, cursor_value, table_end, table_find, table_start};
+use crate::record::Value;
+use crate::table::{Pager, Row};
+
+/// Kind of object a catalog entry describes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryType {
+    Table,
+}
+
+impl EntryType {
+    fn name(&self) -> &'static str {
+        match self {
+            EntryType::Table => "table",
+        }
+    }
+}
+
+/// A row of the system catalog: what the object is, where its tree starts and the
+/// statement that created it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CatalogEntry {
+    pub id: u32,
+    pub entry_type: EntryType,
+    pub name: String,
+    pub root_page_num: u32,
+    pub sql: String,
+}
+
+impl CatalogEntry {
+    fn to_row(&self) -> Row {
+        Row {
+            id: self.id,
+            fields: vec![
+                Value::Text(self.entry_type.name().to_string()),
+                Value::Text(self.name.clone()),
+                Value::Integer(self.root_page_num as i64),
+                Value::Text(self.sql.clone()),
+            ],
+        }
+    }
+
+    fn from_row(row: Row) -> Result<CatalogEntry, Box<dyn Error>> {
+        let corrupt = || std::io::Error::other("Malformed system catalog entry. Corrupt file.");
+        match row.fields.as_slice() {
+            [Value::Text(entry_type), Value::Text(name), Value::Integer(root_page_num), Value::Text(sql)] => Ok(CatalogEntry {
+                id: row.id,
+                entry_type: match entry_type.as_str() {
+                    "table" => EntryType::Table,
+                    _ => return Err(Box::new(corrupt())),
+                },
+                name: name.clone(),
+                root_page_num: u32::try_from(*root_page_num).map_err(|_| corrupt())?,
+                sql: sql.clone(),
+            }),
+            _ => Err(Box::new(corrupt())),
+        }
+    }
+}
+
+/// Every entry of the catalog, in the order they were added.
+pub fn read_catalog(pager: &mut Pager) -> Result<Vec<CatalogEntry>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    let mut cursor = table_start(pager, constants::CATALOG_ROOT_PAGE_NUM)?;
+    while !cursor.end_of_table {
+        if let Some(row) = cursor_row(&mut cursor)? {
+            entries.push(CatalogEntry::from_row(row)?);
+        }
+        cursor_advance(&mut cursor);
+    }
+    Ok(entries)
+}
+
+/// Checks that an object named `name`, created by `sql`, fits in a catalog entry.
+pub fn check_entry(name: &str, sql: &str) -> Result<(), Box<dyn Error>> {
+    if name.len() > constants::CATALOG_NAME_SIZE as usize {
+        return Err(Box::new(std::io::Error::other(format!("Name {} is longer than {} bytes", name, constants::CATALOG_NAME_SIZE))));
+    }
+    if sql.len() > constants::CATALOG_SQL_SIZE as usize {
+        return Err(Box::new(std::io::Error::other(format!("Definition of {} is longer than {} bytes", name, constants::CATALOG_SQL_SIZE))));
+    }
+    Ok(())
+}
+
+/// Records a new object in the catalog and returns its entry.
+pub fn add_entry(pager: &mut Pager, entry_type: EntryType, name: &str, root_page_num: u32, sql: &str) -> Result<CatalogEntry, Box<dyn Error>> {
+    check_entry(name, sql)?;
+
+    let mut cursor = table_end(pager, constants::CATALOG_ROOT_PAGE_NUM)?;
+    let id = match cursor.end_of_table {
+        true => 1,
+        false => cursor_value(&mut cursor).map_or(1, |cell| cell.key + 1),
+    };
+    drop(cursor);
+
+    let entry = CatalogEntry { id, entry_type, name: name.to_string(), root_page_num, sql: sql.to_string() };
+    let mut cursor = table_find(pager, constants::CATALOG_ROOT_PAGE_NUM, id)?;
+    cursor_insert(&mut cursor, &entry.to_row())?;
+    Ok(entry)
+}
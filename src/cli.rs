@@ -7,6 +7,12 @@ pub struct InputBuffer {
     pub buffer: String,
 }
 
+impl Default for InputBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl InputBuffer {
     pub fn new() -> InputBuffer {
         InputBuffer {
@@ -26,6 +32,9 @@ pub fn print_help() {
     println!("Welcome to the monitor.  Commands end with ;");
     println!(".help             Show this message");
     println!(".exit             Exit this program");
+    println!(".schema           Show the statements the system catalog records");
+    println!(".tables           List the tables of the database");
+    println!(".freelist         Show the number of free pages");
 }
 
 /// .
@@ -38,6 +47,6 @@ pub fn print_prompt() {
     io::stdout().flush().unwrap();
 }
 
-pub fn log_debug(table: &table::Table) {
-    println!("Num rows: {:?}", table.pager.pages.len());
+pub fn log_debug(db: &table::Database) {
+    println!("Num rows: {:?}", db.pager.pages.len());
 }
@@ -1,11 +1,9 @@
 #[allow(dead_code)]
 pub const PAGE_SIZE: u32 = 4096;
 
+// pages kept decoded in memory by default, see Pager::set_cache_capacity
 #[allow(dead_code)]
-pub const TABLE_MAX_PAGES: u32 = 100;
-
-#[allow(dead_code)]
-pub const ROW_SIZE: u32 = 292;
+pub const DEFAULT_CACHE_CAPACITY: usize = 2000;
 
 #[allow(dead_code)]
 pub const ID_SIZE: u32 = 4;
@@ -19,12 +17,6 @@ pub const EMAIL_SIZE: u32 = 255;
 #[allow(dead_code)]
 pub const LEAF_NODE_KEY_SIZE: u32 = 4;
 
-#[allow(dead_code)]
-pub const LEAF_NODE_VALUE_SIZE: u32 = ROW_SIZE;
-
-#[allow(dead_code)]
-pub const LEAF_NODE_CELL_SIZE: u32 = LEAF_NODE_KEY_SIZE + ROW_SIZE;
-
 #[allow(dead_code)]
 pub const NODE_TYPE_SIZE: u32 = 1;
 
@@ -46,8 +38,213 @@ pub const NODE_CONTENT_OFFSET: u32 = NODE_CONTENT_LEN_OFFSET + NODE_CONTENT_LEN_
 #[allow(dead_code)]
 pub const NODE_METADATA_SIZE: u32 = NODE_TYPE_SIZE + NODE_PARENT_SIZE + NODE_CONTENT_LEN_SIZE; 
 
+
+#[allow(dead_code)]
+pub const LEAF_NODE_NEXT_LEAF_OFFSET: u32 = NODE_CONTENT_OFFSET;
+
+#[allow(dead_code)]
+pub const LEAF_NODE_NEXT_LEAF_SIZE: u32 = 4;
+
+#[allow(dead_code)]
+pub const LEAF_NODE_HEADER_SIZE: u32 = NODE_METADATA_SIZE + LEAF_NODE_NEXT_LEAF_SIZE;
+
+// a leaf has a u16 pointer per cell after its header; the cells it points to are packed
+// at the end of the page: [key][record size varint][record][first overflow page, if any]
+#[allow(dead_code)]
+pub const LEAF_NODE_CELL_POINTER_SIZE: u32 = 2;
+
+#[allow(dead_code)]
+pub const LEAF_NODE_OVERFLOW_PAGE_SIZE: u32 = 4;
+
+// bytes of a leaf left for cell pointers and cells
+#[allow(dead_code)]
+pub const LEAF_NODE_SPACE: u32 = PAGE_SIZE - LEAF_NODE_HEADER_SIZE;
+
+// largest cell, its pointer included, so a leaf always holds a few of them
+#[allow(dead_code)]
+pub const LEAF_NODE_MAX_CELL_BYTES: u32 = LEAF_NODE_SPACE / 4;
+
+// longest varint a record size needs: a u32 takes at most 5 bytes of 7 bits
+#[allow(dead_code)]
+pub const LEAF_NODE_RECORD_SIZE_MAX_VARINT: u32 = 5;
+
+// bytes of a record kept in its leaf cell; a longer record spills the rest to overflow pages
+#[allow(dead_code)]
+pub const LEAF_NODE_MAX_LOCAL: u32 = LEAF_NODE_MAX_CELL_BYTES - LEAF_NODE_CELL_POINTER_SIZE - LEAF_NODE_KEY_SIZE
+    - LEAF_NODE_RECORD_SIZE_MAX_VARINT - LEAF_NODE_OVERFLOW_PAGE_SIZE;
+
+// a non-root leaf using fewer bytes than this borrows from or merges with a sibling
+#[allow(dead_code)]
+pub const LEAF_NODE_MIN_BYTES: u32 = LEAF_NODE_SPACE / 4;
+
+// overflow page layout: node type, next page of the chain (0 on the last one), data length, data
+#[allow(dead_code)]
+pub const OVERFLOW_NEXT_PAGE_OFFSET: u32 = NODE_TYPE_SIZE;
+
+#[allow(dead_code)]
+pub const OVERFLOW_LEN_OFFSET: u32 = OVERFLOW_NEXT_PAGE_OFFSET + 4;
+
+#[allow(dead_code)]
+pub const OVERFLOW_HEADER_SIZE: u32 = OVERFLOW_LEN_OFFSET + 4;
+
+#[allow(dead_code)]
+pub const OVERFLOW_PAGE_CAPACITY: u32 = PAGE_SIZE - OVERFLOW_HEADER_SIZE;
+
+#[allow(dead_code)]
+pub const INTERNAL_NODE_RIGHT_CHILD_OFFSET: u32 = NODE_CONTENT_OFFSET;
+
+#[allow(dead_code)]
+pub const INTERNAL_NODE_RIGHT_CHILD_SIZE: u32 = 4;
+
+#[allow(dead_code)]
+pub const INTERNAL_NODE_HEADER_SIZE: u32 = NODE_METADATA_SIZE + INTERNAL_NODE_RIGHT_CHILD_SIZE;
+
+#[allow(dead_code)]
+pub const INTERNAL_NODE_CHILD_SIZE: u32 = 4;
+
+#[allow(dead_code)]
+pub const INTERNAL_NODE_KEY_SIZE: u32 = 4;
+
+#[allow(dead_code)]
+pub const INTERNAL_NODE_CELL_SIZE: u32 = INTERNAL_NODE_CHILD_SIZE + INTERNAL_NODE_KEY_SIZE;
+
+#[allow(dead_code)]
+pub const INTERNAL_NODE_MAX_CELLS: u32 = (PAGE_SIZE - INTERNAL_NODE_HEADER_SIZE) / INTERNAL_NODE_CELL_SIZE;
+
+#[allow(dead_code)]
+pub const INTERNAL_NODE_MIN_CELLS: u32 = INTERNAL_NODE_MAX_CELLS / 2;
+
+// page 0 is the file header, the system catalog's B-tree starts right after it
+#[allow(dead_code)]
+pub const FILE_HEADER_PAGE_NUM: u32 = 0;
+
+#[allow(dead_code)]
+pub const CATALOG_ROOT_PAGE_NUM: u32 = 1;
+
+// root of the table a new database starts with, allocated after the catalog
+#[allow(dead_code)]
+pub const ROOT_PAGE_NUM: u32 = 2;
+
+// file header layout: magic string, then u32 fields
+#[allow(dead_code)]
+pub const HEADER_MAGIC: &[u8; 16] = b"SQLighter db\0\0\0\0";
+
+#[allow(dead_code)]
+pub const HEADER_MAGIC_OFFSET: u32 = 0;
+
+#[allow(dead_code)]
+pub const HEADER_VERSION_OFFSET: u32 = HEADER_MAGIC_OFFSET + HEADER_MAGIC.len() as u32;
+
+#[allow(dead_code)]
+pub const HEADER_PAGE_SIZE_OFFSET: u32 = HEADER_VERSION_OFFSET + 4;
+
+// root page of the system catalog
+#[allow(dead_code)]
+pub const HEADER_ROOT_PAGE_OFFSET: u32 = HEADER_PAGE_SIZE_OFFSET + 4;
+
+#[allow(dead_code)]
+pub const HEADER_FREELIST_TRUNK_OFFSET: u32 = HEADER_ROOT_PAGE_OFFSET + 4;
+
+#[allow(dead_code)]
+pub const HEADER_FREELIST_COUNT_OFFSET: u32 = HEADER_FREELIST_TRUNK_OFFSET + 4;
+
+#[allow(dead_code)]
+pub const HEADER_CHANGE_COUNTER_OFFSET: u32 = HEADER_FREELIST_COUNT_OFFSET + 4;
+
+// 0 = rollback journal, 1 = write-ahead log
+#[allow(dead_code)]
+pub const HEADER_JOURNAL_MODE_OFFSET: u32 = HEADER_CHANGE_COUNTER_OFFSET + 4;
+
+// bumped whenever the on-disk layout changes; newer files are refused
+#[allow(dead_code)]
+pub const FORMAT_VERSION: u32 = 5;
+
+// oldest layout still read: version 5 keeps each leaf cell at the length of its record,
+// behind a cell pointer, with overflow pages for long records. Version 4 leaves held
+// fixed 296-byte cells and are refused.
+#[allow(dead_code)]
+pub const MIN_FORMAT_VERSION: u32 = 5;
+
+#[allow(dead_code)]
+pub const FREELIST_TRUNK_NEXT_OFFSET: u32 = 0;
+
+#[allow(dead_code)]
+pub const FREELIST_TRUNK_COUNT_OFFSET: u32 = 4;
+
+#[allow(dead_code)]
+pub const FREELIST_TRUNK_LEAVES_OFFSET: u32 = 8;
+
+#[allow(dead_code)]
+pub const FREELIST_TRUNK_MAX_LEAVES: u32 = (PAGE_SIZE - FREELIST_TRUNK_LEAVES_OFFSET) / 4;
+
+// rollback journal: header (magic, page count of the file before the changes, checksum nonce),
+// then records of [page number][original page image][checksum]
+#[allow(dead_code)]
+pub const JOURNAL_MAGIC: &[u8; 8] = b"SQLjrnl\0";
+
+#[allow(dead_code)]
+pub const JOURNAL_HEADER_SIZE: u32 = JOURNAL_MAGIC.len() as u32 + 4 + 4;
+
+#[allow(dead_code)]
+pub const JOURNAL_RECORD_SIZE: u32 = 4 + PAGE_SIZE + 4;
+
+// write-ahead log: header (magic, page size, salt), then frames of
+// [page number][db size in pages, 0 unless it ends a commit][salt][checksum][page image]
+#[allow(dead_code)]
+pub const WAL_MAGIC: &[u8; 8] = b"SQLwal\0\0";
+
+#[allow(dead_code)]
+pub const WAL_HEADER_SIZE: u32 = WAL_MAGIC.len() as u32 + 4 + 4;
+
+#[allow(dead_code)]
+pub const WAL_FRAME_HEADER_SIZE: u32 = 16;
+
+#[allow(dead_code)]
+pub const WAL_FRAME_SIZE: u32 = WAL_FRAME_HEADER_SIZE + PAGE_SIZE;
+
+// a commit leaving more frames than this in the log copies them back to the database file
+#[allow(dead_code)]
+pub const WAL_AUTOCHECKPOINT_FRAMES: u32 = 1000;
+
+// smallest unit a disk writes atomically; a write cut short by a power loss is torn
+// at one of these boundaries
+#[allow(dead_code)]
+pub const SECTOR_SIZE: u32 = 512;
+
+// database path that opens a new in-memory database instead of a file
+#[allow(dead_code)]
+pub const MEMORY_DB_PATH: &str = ":memory:";
+
+// table the short `insert <id> <username> <email>` form writes to
+#[allow(dead_code)]
+pub const DEFAULT_TABLE_NAME: &str = "users";
+
+// schema of the table a new database starts with
+#[allow(dead_code)]
+pub const DEFAULT_TABLE_SQL: &str = "create table users (id integer, username text(32), email text(255))";
+
+// largest size a text or blob column can be declared with, as in SQLite
+#[allow(dead_code)]
+pub const MAX_VALUE_SIZE: u32 = 1_000_000_000;
+
+// bytes kept for a text column declared without a size
+#[allow(dead_code)]
+pub const DEFAULT_TEXT_SIZE: u32 = 255;
+
+// the system catalog: one row per table, naming its root page and keeping its CREATE text
+#[allow(dead_code)]
+pub const CATALOG_TABLE_NAME: &str = "sqlighter_schema";
+
+#[allow(dead_code)]
+pub const CATALOG_TABLE_SQL: &str = "create table sqlighter_schema (id integer, type text(8), name text(32), rootpage integer, sql text(65536))";
+
+#[allow(dead_code)]
+pub const CATALOG_TYPE_SIZE: u32 = 8;
+
+// longest table name the catalog holds
 #[allow(dead_code)]
-pub const ROWS_PER_PAGE: u32 = (PAGE_SIZE - NODE_METADATA_SIZE)/ ROW_SIZE;
+pub const CATALOG_NAME_SIZE: u32 = 32;
 
+// longest CREATE text the catalog holds
 #[allow(dead_code)]
-pub const TABLE_MAX_ROWS: u32 = ROWS_PER_PAGE * TABLE_MAX_PAGES;
+pub const CATALOG_SQL_SIZE: u32 = 65536;
@@ -1,64 +1,102 @@
 use std::error::Error;
+use std::ops::Bound;
 
-use crate::table::{Row, Table};
-use crate::btree::{Cell, Node, NodeType};
+use crate::record::{decode_record, encode_record};
+use crate::table::{Pager, Row};
+use crate::btree::{cell_record, internal_node_find_child, leaf_node_delete, leaf_node_insert, leaf_node_update, new_cell, Cell, Node, NodeType};
 use crate::utils::binary_search_key;
 
 pub struct Cursor<'a> {
-    pub table: &'a mut Table,
+    pub pager: &'a mut Pager,
+    pub root_page_num: u32, // root of the tree the cursor walks
     pub page_num: u32,
     pub cell_num: u32, 
     pub end_of_table: bool,
+    pub end: Bound<u32>, // the cursor stops before the first key past this bound (in its direction of travel)
+    pinned: Option<u32>, // page kept in the cache for the cursor, see cursor_page
 }
 
+impl Drop for Cursor<'_> {
+    fn drop(&mut self) {
+        if let Some(page_num) = self.pinned {
+            self.pager.unpin(page_num);
+        }
+    }
+}
 
-pub fn table_start(table: &mut Table) -> Cursor {
-    let page_num = table.root_page_num;
-    let root = table.pager.get_page(table.root_page_num).unwrap();
 
-    let num_cells = root.content_len;
-    
-    Cursor {
-        table, 
+fn overflow_in_tree() -> Box<dyn Error> {
+    Box::new(std::io::Error::other("Overflow page linked in a tree. Corrupt file."))
+}
+
+/// Cursor on the first row of the tree rooted at `root_page_num`, i.e. the first cell of the leftmost leaf.
+pub fn table_start(pager: &mut Pager, root_page_num: u32) -> Result<Cursor<'_>, Box<dyn Error>> {
+    let mut page_num = root_page_num;
+    let num_cells = loop {
+        let node = pager.get_page(page_num)?;
+        match &node.node_type {
+            NodeType::NodeInternal { cells, right_child } => {
+                page_num = cells.first().map_or(*right_child, |c| c.child);
+            },
+            NodeType::NodeLeaf { .. } => break node.content_len,
+            NodeType::NodeOverflow { .. } => return Err(overflow_in_tree()),
+        }
+    };
+
+    Ok(Cursor {
+        pager,
+        root_page_num,
         page_num,
         cell_num: 0,
         end_of_table: num_cells == 0,
-    }
+        end: Bound::Unbounded,
+        pinned: None,
+    })
 }
 
-// pub fn table_end(table: &mut Table) -> Cursor {
-//     let page_num = table.root_page_num; 
-//     let root = table.pager.get_page(table.root_page_num).unwrap();
-//     let num_cells = root.content_len; 
+/// Cursor on the last row of the table, meant to be moved with `cursor_retreat`.
+pub fn table_end(pager: &mut Pager, root_page_num: u32) -> Result<Cursor<'_>, Box<dyn Error>> {
+    table_range_rev(pager, root_page_num, (Bound::Unbounded, Bound::Unbounded))
+}
 
-//     Cursor {
-//         table,
-//         page_num,
-//         cell_num: num_cells,
-//         end_of_table: num_cells == ROWS_PER_PAGE,
-//     }
-// }
+/// Pins the page under the cursor, releasing the one it was on before.
+fn cursor_pin(cursor: &mut Cursor) {
+    if cursor.pinned != Some(cursor.page_num) {
+        if let Some(page_num) = cursor.pinned {
+            cursor.pager.unpin(page_num);
+        }
+        cursor.pager.pin(cursor.page_num);
+        cursor.pinned = Some(cursor.page_num);
+    }
+}
 
-pub fn cursor_page<'a>(cursor: &'a mut Cursor) -> Result<&'a mut Node, Box<dyn Error>> {
+pub fn cursor_page<'a>(cursor: &'a mut Cursor) -> Result<&'a Node, Box<dyn Error>> {
     // current page pointed to by cursor
-    cursor.table.pager.get_page(cursor.page_num) 
+    cursor_pin(cursor);
+    cursor.pager.get_page(cursor.page_num) 
 } 
 
-pub fn cursor_value<'a>(cursor: &'a mut Cursor) -> Option<Cell> {
+pub fn cursor_page_mut<'a>(cursor: &'a mut Cursor) -> Result<&'a mut Node, Box<dyn Error>> {
+    cursor_pin(cursor);
+    cursor.pager.get_page_mut(cursor.page_num)
+}
+
+pub fn cursor_value(cursor: &mut Cursor) -> Option<Cell> {
     // current value pointed to by cursor
+    let cell_num = cursor.cell_num;
     if let Ok(node) = cursor_page(cursor) {
         
-        match node.clone().node_type {
-            NodeType::NodeLeaf(cells) => {
-                if cursor.cell_num >= cells.len() as u32 {
+        match &node.node_type {
+            NodeType::NodeLeaf { cells, .. } => {
+                if cell_num >= cells.len() as u32 {
                     return None;
                 }
 
-                let cell = cells[cursor.cell_num as usize].clone();
+                let cell = cells[cell_num as usize].clone();
                 return Some(cell)
             },
-            NodeType::NodeInternal(_)=> {
-                return None 
+            _ => {
+                return None
             }
         }
     }
@@ -66,41 +104,230 @@ pub fn cursor_value<'a>(cursor: &'a mut Cursor) -> Option<Cell> {
     None
 }
 
-pub fn table_find(table: &mut Table, key: u32) -> Result<Cursor, Box<dyn Error>> {
-    let mut cursor = table_start(table);
-    let root = cursor_page(&mut cursor)?;
-    match root.clone().node_type {
-        NodeType::NodeLeaf(cells) => {
+/// Cursor on the leaf cell holding `key`, or on the position where `key` would be inserted.
+pub fn table_find(pager: &mut Pager, root_page_num: u32, key: u32) -> Result<Cursor<'_>, Box<dyn Error>> {
+    let mut cursor = Cursor {
+        pager,
+        root_page_num,
+        page_num: root_page_num,
+        cell_num: 0,
+        end_of_table: false,
+        end: Bound::Unbounded,
+        pinned: None,
+    };
+    cursor_descend(&mut cursor, key)?;
+    Ok(cursor)
+}
 
-            let idx = binary_search_key(cells.clone(), key); 
-            cursor.cell_num = idx;
-            if idx < cells.len() as u32 && cells[idx as usize].key == key {
-                eprintln!("Key already exists");
-                return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Key already exists")));
-            }
-            return Ok(cursor);
+/// Cursor over the rows whose key falls in `range`, starting at the first one.
+pub fn table_range(pager: &mut Pager, root_page_num: u32, range: (Bound<u32>, Bound<u32>)) -> Result<Cursor<'_>, Box<dyn Error>> {
+    let mut cursor = Cursor {
+        pager,
+        root_page_num,
+        page_num: root_page_num,
+        cell_num: 0,
+        end_of_table: false,
+        end: range.1,
+        pinned: None,
+    };
+    match range.0 {
+        Bound::Included(key) => cursor_seek(&mut cursor, key)?,
+        Bound::Excluded(key) => match key.checked_add(1) {
+            Some(key) => cursor_seek(&mut cursor, key)?,
+            None => cursor.end_of_table = true,
         },
-        NodeType::NodeInternal(_) => unimplemented!("Internal nodes not implemented")
+        Bound::Unbounded => cursor_seek(&mut cursor, 0)?,
+    }
+    Ok(cursor)
+}
 
+/// Cursor on the last row whose key falls in `range`, meant to be moved with `cursor_retreat`.
+pub fn table_range_rev(pager: &mut Pager, root_page_num: u32, range: (Bound<u32>, Bound<u32>)) -> Result<Cursor<'_>, Box<dyn Error>> {
+    let mut cursor = Cursor {
+        pager,
+        root_page_num,
+        page_num: root_page_num,
+        cell_num: 0,
+        end_of_table: false,
+        end: range.0,
+        pinned: None,
+    };
+    // go to the first key past the range, then step back once
+    match range.1 {
+        Bound::Included(key) if key < u32::MAX => cursor_descend(&mut cursor, key + 1)?,
+        Bound::Excluded(key) => cursor_descend(&mut cursor, key)?,
+        _ => cursor_descend_last(&mut cursor)?,
     }
+    cursor_retreat(&mut cursor);
+    Ok(cursor)
+}
+
+fn cursor_descend_last(cursor: &mut Cursor) -> Result<(), Box<dyn Error>> {
+    let mut page_num = cursor.root_page_num;
+    let cell_num = loop {
+        let node = cursor.pager.get_page(page_num)?;
+        match &node.node_type {
+            NodeType::NodeInternal { right_child, .. } => page_num = *right_child,
+            NodeType::NodeLeaf { .. } => break node.content_len,
+            NodeType::NodeOverflow { .. } => return Err(overflow_in_tree()),
+        }
+    };
+    cursor.page_num = page_num;
+    cursor.cell_num = cell_num;
+    Ok(())
 }
 
+fn cursor_descend(cursor: &mut Cursor, key: u32) -> Result<(), Box<dyn Error>> {
+    let mut page_num = cursor.root_page_num;
+    let cell_num = loop {
+        match &cursor.pager.get_page(page_num)?.node_type {
+            NodeType::NodeInternal { cells, right_child } => {
+                page_num = internal_node_find_child(cells, *right_child, key);
+            },
+            NodeType::NodeLeaf { cells, .. } => break binary_search_key(cells, key),
+            NodeType::NodeOverflow { .. } => return Err(overflow_in_tree()),
+        }
+    };
+    cursor.page_num = page_num;
+    cursor.cell_num = cell_num;
+    Ok(())
+}
 
+/// Moves the cursor to the first row whose key is >= `key`.
+pub fn cursor_seek(cursor: &mut Cursor, key: u32) -> Result<(), Box<dyn Error>> {
+    cursor_descend(cursor, key)?;
+    cursor.end_of_table = false;
+    cursor_settle(cursor);
+    Ok(())
+}
 
+/// Moves past the end of exhausted leaves and flags the end of the table (or of the range).
+fn cursor_settle(cursor: &mut Cursor) {
+    loop {
+        let cell_num = cursor.cell_num as usize;
+        let end = cursor.end;
+        let node = match cursor_page(cursor) {
+            Ok(node) => node,
+            Err(_) => {
+                cursor.end_of_table = true;
+                return;
+            },
+        };
+        match &node.node_type {
+            NodeType::NodeLeaf { cells, .. } if cell_num < cells.len() => {
+                let key = cells[cell_num].key;
+                cursor.end_of_table = match end {
+                    Bound::Included(end) => key > end,
+                    Bound::Excluded(end) => key >= end,
+                    Bound::Unbounded => false,
+                };
+                return;
+            },
+            NodeType::NodeLeaf { next_leaf, .. } if *next_leaf != 0 => {
+                cursor.page_num = *next_leaf;
+                cursor.cell_num = 0;
+            },
+            _ => {
+                cursor.end_of_table = true;
+                return;
+            },
+        }
+    }
+}
+
+/// Moves to the previous row. Leaves have no back pointer, so crossing to the previous
+/// leaf climbs through the parents up to the first ancestor with a child on the left.
+pub fn cursor_retreat(cursor: &mut Cursor) {
+    if cursor.cell_num > 0 {
+        cursor.cell_num -= 1;
+    } else {
+        // an emptied leaf can remain in the tree, so keep going left until a leaf has a cell
+        loop {
+            match previous_leaf(cursor) {
+                Ok(Some((page_num, 0))) => cursor.page_num = page_num,
+                Ok(Some((page_num, num_cells))) => {
+                    cursor.page_num = page_num;
+                    cursor.cell_num = num_cells - 1;
+                    break;
+                },
+                _ => {
+                    cursor.end_of_table = true;
+                    return;
+                },
+            }
+        }
+    }
+
+    let end = cursor.end;
+    cursor.end_of_table = match cursor_value(cursor) {
+        Some(cell) => match end {
+            Bound::Included(end) => cell.key < end,
+            Bound::Excluded(end) => cell.key <= end,
+            Bound::Unbounded => false,
+        },
+        None => true,
+    };
+}
+
+fn previous_leaf(cursor: &mut Cursor) -> Result<Option<(u32, u32)>, Box<dyn Error>> {
+    let pager = &mut *cursor.pager;
+    let mut child_page_num = cursor.page_num;
+    let mut page_num = loop {
+        if child_page_num == cursor.root_page_num {
+            return Ok(None);
+        }
+        let parent_page_num = pager.get_page(child_page_num)?.parent;
+        let children = match &pager.get_page(parent_page_num)?.node_type {
+            NodeType::NodeInternal { cells, right_child } => cells.iter().map(|c| c.child).chain([*right_child]).collect::<Vec<u32>>(),
+            _ => return Err(Box::new(std::io::Error::other("Parent of a node must be an internal node"))),
+        };
+        match children.iter().position(|&c| c == child_page_num) {
+            Some(idx) if idx > 0 => break children[idx - 1],
+            _ => child_page_num = parent_page_num,
+        }
+    };
+
+    loop {
+        let node = pager.get_page(page_num)?;
+        match &node.node_type {
+            NodeType::NodeInternal { right_child, .. } => page_num = *right_child,
+            NodeType::NodeLeaf { .. } => return Ok(Some((page_num, node.content_len))),
+            NodeType::NodeOverflow { .. } => return Err(overflow_in_tree()),
+        }
+    }
+}
+
+/// Moves to the next row, following the sibling pointer once the current leaf runs out.
 pub fn cursor_advance(cursor: &mut Cursor) {
     cursor.cell_num += 1;
-    let n = cursor.cell_num;
-    if let Ok(node) = cursor_page(cursor) {
-        
-        if n >= node.content_len {
-            cursor.end_of_table = true;
-        }
-    } 
+    cursor_settle(cursor);
 }
 
-pub fn cursor_insert(cursor: &mut Cursor, row: Row) -> Result<(), Box<dyn Error>> {
+/// The row under the cursor: the key of the cell and the values of its record.
+pub fn cursor_row(cursor: &mut Cursor) -> Result<Option<Row>, Box<dyn Error>> {
+    match cursor_value(cursor) {
+        Some(cell) => Ok(Some(Row { id: cell.key, fields: decode_record(&cell_record(cursor.pager, &cell)?)? })),
+        None => Ok(None),
+    }
+}
+
+/// Inserts `row` at the cursor position, which must be where its id belongs.
+pub fn cursor_insert(cursor: &mut Cursor, row: &Row) -> Result<(), Box<dyn Error>> {
+    let cell = new_cell(cursor.pager, row.id, encode_record(&row.fields))?;
+    leaf_node_insert(cursor.pager, cursor.root_page_num, cursor.page_num, cursor.cell_num, cell)
+}
 
-    let cell = Cell::new(row);
-    let node = cursor_page(cursor)?;
-    node.insert_cell(cell)
-}
\ No newline at end of file
+pub fn cursor_delete(cursor: &mut Cursor) -> Result<(), Box<dyn Error>> {
+    let root_page_num = cursor.root_page_num;
+    leaf_node_delete(cursor.pager, root_page_num, cursor.page_num, cursor.cell_num)
+}
+
+/// Rewrites the row under the cursor. The id must not change.
+pub fn cursor_update(cursor: &mut Cursor, row: &Row) -> Result<(), Box<dyn Error>> {
+    match cursor_value(cursor) {
+        Some(cell) if cell.key == row.id => (),
+        _ => return Err(Box::new(std::io::Error::other("No row with this id at cursor position"))),
+    }
+    let cell = new_cell(cursor.pager, row.id, encode_record(&row.fields))?;
+    leaf_node_update(cursor.pager, cursor.root_page_num, cursor.page_num, cursor.cell_num, cell)
+}
@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::constants;
+use crate::storage::{Storage, Vfs};
+use crate::utils::Page;
+
+/// Rollback journal of the flush in progress. The original image of every page is saved
+/// (and synced) here before the page is overwritten in the database file, and the journal
+/// is deleted once the database file is synced: a journal left behind means the file may
+/// hold a mix of old and new pages, and is rolled back by `recover`.
+pub struct Journal {
+    path: PathBuf,
+    file: Box<dyn Storage>,
+    end: u64, // where the next record goes
+    original_pages: u32, // pages past this were not in the file yet, truncating them is enough
+    nonce: u32, // seeds the record checksums, so stale or zeroed bytes never pass for a record
+    saved: HashSet<u32>,
+    synced: bool,
+}
+
+pub fn journal_path(db_path: &Path) -> PathBuf {
+    let mut path = db_path.to_path_buf().into_os_string();
+    path.push("-journal");
+    PathBuf::from(path)
+}
+
+fn checksum(nonce: u32, page_num: u32, page: &[u8]) -> u32 {
+    page.chunks(4).fold(nonce ^ page_num, |sum, word| {
+        sum.rotate_left(5) ^ u32::from_le_bytes(word.try_into().unwrap())
+    })
+}
+
+/// Never 0: a record of zeros, left by a write that was lost, must not check out.
+fn new_nonce() -> u32 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    nanos | 1
+}
+
+impl Journal {
+    pub fn create(vfs: &dyn Vfs, db_path: &Path, original_pages: u32) -> Result<Journal, Box<dyn Error>> {
+        let path = journal_path(db_path);
+        let mut file = vfs.open(&path)?;
+        let nonce = new_nonce();
+        file.set_size(0)?;
+        let mut header = constants::JOURNAL_MAGIC.to_vec();
+        header.extend_from_slice(&original_pages.to_le_bytes());
+        header.extend_from_slice(&nonce.to_le_bytes());
+        file.write_at(0, &header)?;
+        Ok(Journal {
+            path,
+            file,
+            end: constants::JOURNAL_HEADER_SIZE as u64,
+            original_pages,
+            nonce,
+            saved: HashSet::new(),
+            synced: false,
+        })
+    }
+
+    /// Whether the original image of `page_num` still has to be saved before overwriting it.
+    pub fn needs(&self, page_num: u32) -> bool {
+        page_num < self.original_pages && !self.saved.contains(&page_num)
+    }
+
+    pub fn append(&mut self, page_num: u32, page: &Page) -> Result<(), Box<dyn Error>> {
+        let mut record = Vec::with_capacity(constants::JOURNAL_RECORD_SIZE as usize);
+        record.extend_from_slice(&page_num.to_le_bytes());
+        record.extend_from_slice(page);
+        record.extend_from_slice(&checksum(self.nonce, page_num, page).to_le_bytes());
+        self.file.write_at(self.end, &record)?;
+        self.end += constants::JOURNAL_RECORD_SIZE as u64;
+        self.saved.insert(page_num);
+        self.synced = false;
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.synced {
+            self.file.sync()?;
+            self.synced = true;
+        }
+        Ok(())
+    }
+
+    /// Commits the flush: the changes can no longer be rolled back.
+    pub fn delete(self, vfs: &dyn Vfs) -> Result<(), Box<dyn Error>> {
+        vfs.remove(&self.path)
+    }
+}
+
+/// Rolls the database file back to its state before an interrupted flush, if a journal was
+/// left behind. Records cut short by the crash (bad checksum) were never applied to the
+/// file and are ignored. Returns whether a journal was found.
+pub fn recover(vfs: &dyn Vfs, db_path: &Path, db_file: &mut dyn Storage) -> Result<bool, Box<dyn Error>> {
+    let path = journal_path(db_path);
+    if !vfs.exists(&path) {
+        return Ok(false);
+    }
+    let mut file = vfs.open(&path)?;
+    let mut data = vec![0u8; file.size()? as usize];
+    file.read_at(0, &mut data)?;
+
+    let header_size = constants::JOURNAL_HEADER_SIZE as usize;
+    // without a complete header nothing was written to the database file yet
+    if data.len() >= header_size && &data[..constants::JOURNAL_MAGIC.len()] == constants::JOURNAL_MAGIC {
+        let magic_len = constants::JOURNAL_MAGIC.len();
+        let original_pages = u32::from_le_bytes(data[magic_len..magic_len + 4].try_into()?);
+        let nonce = u32::from_le_bytes(data[magic_len + 4..header_size].try_into()?);
+        for record in data[header_size..].chunks_exact(constants::JOURNAL_RECORD_SIZE as usize) {
+            let page_num = u32::from_le_bytes(record[..4].try_into()?);
+            let page = &record[4..4 + constants::PAGE_SIZE as usize];
+            let stored = u32::from_le_bytes(record[4 + constants::PAGE_SIZE as usize..].try_into()?);
+            if stored != checksum(nonce, page_num, page) || page_num >= original_pages {
+                break;
+            }
+            db_file.write_at(page_num as u64 * constants::PAGE_SIZE as u64, page)?;
+        }
+        db_file.set_size(original_pages as u64 * constants::PAGE_SIZE as u64)?;
+        db_file.sync()?;
+    }
+
+    vfs.remove(&path)?;
+    Ok(true)
+}
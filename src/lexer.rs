@@ -0,0 +1,287 @@
+use crate::parser::ParseError;
+use crate::sql::PrepareResult;
+
+/// Line and column of a token in the statement text, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Keyword {
+    And,
+    Asc,
+    Begin,
+    By,
+    Commit,
+    Create,
+    Delete,
+    Desc,
+    From,
+    Insert,
+    Into,
+    Limit,
+    Null,
+    Order,
+    Pragma,
+    Release,
+    Rollback,
+    Savepoint,
+    Select,
+    Set,
+    Table,
+    To,
+    Transaction,
+    Update,
+    Vacuum,
+    Values,
+    Where,
+}
+
+const KEYWORDS: [(&str, Keyword); 27] = [
+    ("and", Keyword::And),
+    ("asc", Keyword::Asc),
+    ("begin", Keyword::Begin),
+    ("by", Keyword::By),
+    ("commit", Keyword::Commit),
+    ("create", Keyword::Create),
+    ("delete", Keyword::Delete),
+    ("desc", Keyword::Desc),
+    ("from", Keyword::From),
+    ("insert", Keyword::Insert),
+    ("into", Keyword::Into),
+    ("limit", Keyword::Limit),
+    ("null", Keyword::Null),
+    ("order", Keyword::Order),
+    ("pragma", Keyword::Pragma),
+    ("release", Keyword::Release),
+    ("rollback", Keyword::Rollback),
+    ("savepoint", Keyword::Savepoint),
+    ("select", Keyword::Select),
+    ("set", Keyword::Set),
+    ("table", Keyword::Table),
+    ("to", Keyword::To),
+    ("transaction", Keyword::Transaction),
+    ("update", Keyword::Update),
+    ("vacuum", Keyword::Vacuum),
+    ("values", Keyword::Values),
+    ("where", Keyword::Where),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Keyword(Keyword),
+    Identifier(String), // a bare word, or a "quoted" name
+    String(String), // 'quoted', with '' standing for a single quote
+    Integer(i64),
+    Real(f64),
+    Blob(Vec<u8>), // x'hex digits'
+    Star,
+    Comma,
+    LeftParen,
+    RightParen,
+    Semicolon,
+    Equals,
+    NotEquals,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    End,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String, // as written, quotes included
+    pub position: Position,
+}
+
+/// Characters that can continue a bare word. `@` and `.` are allowed so unquoted emails
+/// keep working.
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '@' || c == '.'
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    input: &'a str,
+    position: Position,
+}
+
+impl Lexer<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map_or(self.input.len(), |(i, _)| *i)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    fn bump_while(&mut self, accept: impl Fn(char) -> bool) {
+        while self.peek().is_some_and(&accept) {
+            self.bump();
+        }
+    }
+
+    /// Reads a quoted string or name, the opening quote already consumed.
+    fn quoted(&mut self, quote: char, start: Position) -> Result<String, ParseError> {
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => {
+                    if self.peek() != Some(quote) {
+                        return Ok(value);
+                    }
+                    self.bump();
+                    value.push(quote);
+                },
+                Some(c) => value.push(c),
+                None => return Err(ParseError::new(PrepareResult::PrepareSyntaxError, start, "unterminated quoted text")),
+            }
+        }
+    }
+
+    fn number(&mut self, text: &str, start: Position) -> Result<TokenKind, ParseError> {
+        let invalid = || ParseError::new(PrepareResult::PrepareSyntaxError, start, format!("invalid number '{}'", text));
+        if text.contains('.') {
+            return text.parse::<f64>().map(TokenKind::Real).map_err(|_| invalid());
+        }
+        text.parse::<i64>().map(TokenKind::Integer).map_err(|_| invalid())
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, ParseError> {
+        self.bump_while(char::is_whitespace);
+        let start = self.position;
+        let begin = self.offset();
+        let c = match self.bump() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let kind = match c {
+            '*' => TokenKind::Star,
+            ',' => TokenKind::Comma,
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            ';' => TokenKind::Semicolon,
+            '=' => TokenKind::Equals,
+            '!' if self.peek() == Some('=') => {
+                self.bump();
+                TokenKind::NotEquals
+            },
+            '<' => match self.peek() {
+                Some('=') => {
+                    self.bump();
+                    TokenKind::LessOrEqual
+                },
+                Some('>') => {
+                    self.bump();
+                    TokenKind::NotEquals
+                },
+                _ => TokenKind::Less,
+            },
+            '>' => match self.peek() {
+                Some('=') => {
+                    self.bump();
+                    TokenKind::GreaterOrEqual
+                },
+                _ => TokenKind::Greater,
+            },
+            '\'' => TokenKind::String(self.quoted('\'', start)?),
+            '"' => TokenKind::Identifier(self.quoted('"', start)?),
+            // a sign only belongs to a number written right after it
+            '-' if self.peek().is_some_and(|c| c.is_ascii_digit()) => {
+                self.bump_while(|c| c.is_ascii_digit() || c == '.');
+                let text = self.input[begin..self.offset()].to_string();
+                self.number(&text, start)?
+            },
+            c if c.is_ascii_digit() => {
+                self.bump_while(|c| c.is_ascii_digit() || c == '.');
+                let text = self.input[begin..self.offset()].to_string();
+                self.number(&text, start)?
+            },
+            'x' | 'X' if self.peek() == Some('\'') => {
+                self.bump();
+                let hex = self.quoted('\'', start)?;
+                TokenKind::Blob(blob(&hex, start)?)
+            },
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                self.bump_while(is_word_char);
+                let word = &self.input[begin..self.offset()];
+                match KEYWORDS.iter().find(|(name, _)| name.eq_ignore_ascii_case(word)) {
+                    Some((_, keyword)) => TokenKind::Keyword(*keyword),
+                    None => TokenKind::Identifier(word.to_string()),
+                }
+            },
+            c => return Err(ParseError::new(PrepareResult::PrepareSyntaxError, start, format!("unexpected character '{}'", c))),
+        };
+        let text = self.input[begin..self.offset()].to_string();
+        Ok(Some(Token { kind, text, position: start }))
+    }
+}
+
+/// Bytes of a blob literal, two hex digits each.
+fn blob(hex: &str, start: Position) -> Result<Vec<u8>, ParseError> {
+    let invalid = || ParseError::new(PrepareResult::PrepareSyntaxError, start, format!("invalid blob x'{}'", hex));
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..hex.len()).step_by(2).map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()).ok_or_else(invalid)).collect()
+}
+
+/// Splits `input` at whitespace, without reading the words: each one becomes an
+/// `Identifier` token as written, whatever characters it holds.
+pub fn words(input: &str) -> Vec<Token> {
+    let mut words = Vec::new();
+    let mut position = Position { line: 1, column: 1 };
+    let mut word: Option<(String, Position)> = None;
+    for c in input.chars() {
+        match (c.is_whitespace(), word.as_mut()) {
+            (true, Some(_)) => {
+                let (text, start) = word.take().unwrap();
+                words.push(Token { kind: TokenKind::Identifier(text.clone()), text, position: start });
+            },
+            (true, None) => (),
+            (false, Some((text, _))) => text.push(c),
+            (false, None) => word = Some((c.to_string(), position)),
+        }
+        if c == '\n' {
+            position.line += 1;
+            position.column = 1;
+        } else {
+            position.column += 1;
+        }
+    }
+    if let Some((text, start)) = word {
+        words.push(Token { kind: TokenKind::Identifier(text.clone()), text, position: start });
+    }
+    words
+}
+
+/// Splits `input` into tokens, ending with a `TokenKind::End` token.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut lexer = Lexer {
+        chars: input.char_indices().peekable(),
+        input,
+        position: Position { line: 1, column: 1 },
+    };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    tokens.push(Token { kind: TokenKind::End, text: String::new(), position: lexer.position });
+    Ok(tokens)
+}
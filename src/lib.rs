@@ -1,7 +1,17 @@
 pub mod sql;
+pub mod ast;
+pub mod lexer;
+pub mod parser;
 pub mod table;
+pub mod schema;
+pub mod record;
+pub mod catalog;
 pub mod constants;
 pub mod cli;
 pub mod cursor;
 pub mod btree;
-pub mod utils;
\ No newline at end of file
+pub mod cache;
+pub mod journal;
+pub mod storage;
+pub mod utils;
+pub mod wal;
\ No newline at end of file
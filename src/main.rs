@@ -1,81 +1,104 @@
-mod cli;
-mod sql;
-mod table;
-mod cursor;
-mod utils;
-mod btree;
-mod constants;
 use std::env;
+use std::rc::Rc;
 
-use crate::sql::MetaCommandResult;
-use crate::sql::{prepare_statement, execute_statement, PrepareResult};
-use crate::sql::Statement;
+use sqlite_rs::{cli, sql, table};
+use sqlite_rs::catalog::read_catalog;
+use sqlite_rs::sql::MetaCommandResult;
+use sqlite_rs::sql::{prepare_for, execute_statement, PrepareResult};
+use sqlite_rs::sql::Statement;
+use sqlite_rs::storage::MmapVfs;
 
 fn main() {
     cli::print_help();
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
-        println!("Usage: {} <db_file>", args[0]);
+        println!("Usage: {} <db_file> [--mmap]", args[0]);
         return;
     }
     let db_file = &args[1];
 
     let mut input = cli::InputBuffer::new();
 
-    let mut table = table::Table::db_open(db_file.to_string());
+    // --mmap reads pages straight from a memory map of the file
+    let opened = match args.get(2).map(String::as_str) {
+        Some("--mmap") => table::Database::open_with_vfs(db_file.to_string(), Rc::new(MmapVfs)),
+        _ => table::Database::open(db_file.to_string()),
+    };
+    let mut db = match opened {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("Could not open {}: {}", db_file, e);
+            std::process::exit(1);
+        }
+    };
 
     loop {
         cli::print_prompt();
         cli::read_input(&mut input);
-        handle_statement(input.buffer.clone(), &mut table);
+        handle_statement(input.buffer.clone(), &mut db);
     }
 }
 
-pub fn handle_statement(cmd: String, table: &mut table::Table) {
+pub fn handle_statement(cmd: String, db: &mut table::Database) {
     let mut statement: Statement = Statement {
         statement_type: sql::StatementType::Invalid(cmd.clone()),
         row_to_insert: table::Row::new(),
+        table: String::new(),
     };
 
     if cmd.starts_with(".") {
-        match do_meta_command(cmd, table) {
+        match do_meta_command(cmd, db) {
             MetaCommandResult::MetaCommandSuccess => (),
             MetaCommandResult::MetaCommandUnrecognizedCommand(cmd) => {
                 println!("Unrecognized command '{}'", cmd);
             }
         }
     } else {
-        match prepare_statement(cmd.as_str(), &mut statement) {
-            PrepareResult::PrepareSuccess =>{ 
-                execute_statement(statement, table);
-                ()
+        match prepare_for(&db.tables, cmd.as_str(), &mut statement) {
+            Ok(()) => {
+                execute_statement(statement, db);
             },
-            PrepareResult::PrepareSyntaxError => {
-                println!("Syntax error. Could not parse statement '{}'", cmd)
-            }
-            PrepareResult::PrepareUnrecognizedStatement => {
-                println!("Unrecognized keyword at start of '{}'", cmd)
-            }
-            PrepareResult::PrepareStringTooLong => {
-                println!("String is too long")
-            }
-            PrepareResult::PrepareNegativeID => {
-                println!("ID must be positive")
+            Err(err) => match err.result {
+                PrepareResult::PrepareUnrecognizedStatement => {
+                    println!("Unrecognized keyword at start of '{}'", cmd)
+                }
+                PrepareResult::PrepareStringTooLong => {
+                    println!("String is too long: {}", err)
+                }
+                PrepareResult::PrepareNegativeID => {
+                    println!("ID must be positive: {}", err)
+                }
+                _ => {
+                    println!("Syntax error: {}", err)
+                }
             }
         }
     }
 }
 
 
-pub fn do_meta_command(cmd: String, table: &mut table::Table) -> MetaCommandResult {
+pub fn do_meta_command(cmd: String, db: &mut table::Database) -> MetaCommandResult {
     if cmd == ".exit" {
-        table.db_close();
+        db.close();
         std::process::exit(0);
     } else if cmd == ".help" {
         cli::print_help();
         MetaCommandResult::MetaCommandSuccess
+    } else if cmd == ".schema" {
+        match read_catalog(&mut db.pager) {
+            Ok(entries) => entries.iter().for_each(|entry| println!("{};", entry.sql)),
+            Err(e) => println!("Error reading the system catalog: {}", e),
+        }
+        MetaCommandResult::MetaCommandSuccess
+    } else if cmd == ".tables" {
+        let names = db.tables.iter().map(|table| table.schema.name.as_str()).collect::<Vec<&str>>();
+        println!("{}", names.join(" "));
+        MetaCommandResult::MetaCommandSuccess
+    } else if cmd == ".freelist" {
+        println!("Free pages: {}", db.pager.free_page_count());
+        MetaCommandResult::MetaCommandSuccess
     } else if cmd == ".debug" {
-        cli::log_debug(table);
+        cli::log_debug(db);
         MetaCommandResult::MetaCommandSuccess
     } else {
         MetaCommandResult::MetaCommandUnrecognizedCommand(cmd)
@@ -0,0 +1,360 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::ast::{Assignment, ColumnDef, CompareOp, Condition, Constant, CreateTable, Delete, Ident, Insert, Literal, OrderBy, Pragma, Select, Stmt, Update};
+use crate::constants;
+use crate::lexer::{tokenize, words, Keyword, Position, Token, TokenKind};
+use crate::sql::PrepareResult;
+use crate::table::{JournalMode, Order};
+
+/// Why a statement could not be prepared, and where.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub result: PrepareResult,
+    pub position: Position,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new(result: PrepareResult, position: Position, message: impl Into<String>) -> ParseError {
+        ParseError { result, position, message: message.into() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.position.line, self.position.column)
+    }
+}
+
+impl Error for ParseError {}
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+fn describe(token: &Token) -> String {
+    match token.kind {
+        TokenKind::End => "end of input".to_string(),
+        _ => format!("'{}'", token.text),
+    }
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next]
+    }
+
+    /// Moves past the current token and returns it. `End` is never moved past.
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.next].clone();
+        if token.kind != TokenKind::End {
+            self.next += 1;
+        }
+        token
+    }
+
+    fn error(&self, expected: &str) -> ParseError {
+        let token = self.peek();
+        ParseError::new(PrepareResult::PrepareSyntaxError, token.position,
+            format!("expected {}, found {}", expected, describe(token)))
+    }
+
+    fn at_keyword(&self, keyword: Keyword) -> bool {
+        self.peek().kind == TokenKind::Keyword(keyword)
+    }
+
+    fn accept_keyword(&mut self, keyword: Keyword) -> bool {
+        let found = self.at_keyword(keyword);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: Keyword, text: &str) -> Result<(), ParseError> {
+        if !self.accept_keyword(keyword) {
+            return Err(self.error(&format!("'{}'", text)));
+        }
+        Ok(())
+    }
+
+    fn accept(&mut self, kind: TokenKind) -> bool {
+        let found = self.peek().kind == kind;
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn expect(&mut self, kind: TokenKind, text: &str) -> Result<(), ParseError> {
+        if !self.accept(kind) {
+            return Err(self.error(&format!("'{}'", text)));
+        }
+        Ok(())
+    }
+
+    fn ident(&mut self, what: &str) -> Result<Ident, ParseError> {
+        match &self.peek().kind {
+            TokenKind::Identifier(name) => {
+                let ident = Ident { name: name.clone(), position: self.peek().position };
+                self.advance();
+                Ok(ident)
+            },
+            _ => Err(self.error(what)),
+        }
+    }
+
+    /// A literal. Bare words are taken as text, so `insert 1 bob bob@mail.com` works unquoted.
+    fn value(&mut self) -> Result<Constant, ParseError> {
+        let token = self.peek().clone();
+        let literal = match token.kind {
+            TokenKind::Keyword(Keyword::Null) => Literal::Null,
+            TokenKind::Integer(n) => Literal::Integer(n),
+            TokenKind::Real(x) => Literal::Real(x),
+            TokenKind::String(text) | TokenKind::Identifier(text) => Literal::Text(text),
+            TokenKind::Blob(bytes) => Literal::Blob(bytes),
+            _ => return Err(self.error("a value")),
+        };
+        self.advance();
+        Ok(Constant { literal, position: token.position })
+    }
+
+    fn at_statement_end(&self) -> bool {
+        matches!(self.peek().kind, TokenKind::End | TokenKind::Semicolon)
+    }
+
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        let token = self.advance();
+        let keyword = match token.kind {
+            TokenKind::Keyword(keyword) => keyword,
+            TokenKind::End => return Err(ParseError::new(PrepareResult::PrepareUnrecognizedStatement, token.position, "empty statement")),
+            _ => return Err(ParseError::new(PrepareResult::PrepareUnrecognizedStatement, token.position,
+                format!("unrecognized keyword {}", describe(&token)))),
+        };
+        match keyword {
+            Keyword::Select => self.select(),
+            Keyword::Insert => self.insert(),
+            Keyword::Delete => self.delete(),
+            Keyword::Update => self.update(),
+            Keyword::Create => self.create(),
+            Keyword::Vacuum => Ok(Stmt::Vacuum),
+            Keyword::Begin => {
+                self.accept_keyword(Keyword::Transaction);
+                Ok(Stmt::Begin)
+            },
+            Keyword::Commit => {
+                self.accept_keyword(Keyword::Transaction);
+                Ok(Stmt::Commit)
+            },
+            Keyword::Rollback => {
+                if self.accept_keyword(Keyword::To) {
+                    self.accept_keyword(Keyword::Savepoint);
+                    return Ok(Stmt::RollbackTo(self.ident("a savepoint name")?));
+                }
+                self.accept_keyword(Keyword::Transaction);
+                Ok(Stmt::Rollback)
+            },
+            Keyword::Savepoint => Ok(Stmt::Savepoint(self.ident("a savepoint name")?)),
+            Keyword::Release => {
+                self.accept_keyword(Keyword::Savepoint);
+                Ok(Stmt::Release(self.ident("a savepoint name")?))
+            },
+            Keyword::Pragma => self.pragma(),
+            _ => Err(ParseError::new(PrepareResult::PrepareUnrecognizedStatement, token.position,
+                format!("unrecognized keyword {}", describe(&token)))),
+        }
+    }
+
+    /// `select` alone, or `select * from t [where ...] [order by c [asc|desc]] [limit n]`.
+    fn select(&mut self) -> Result<Stmt, ParseError> {
+        if self.at_statement_end() {
+            return Ok(Stmt::Select(Select { table: None, filter: Vec::new(), order_by: None, limit: None }));
+        }
+        self.expect(TokenKind::Star, "*")?;
+        self.expect_keyword(Keyword::From, "from")?;
+        let table = self.ident("a table name")?;
+        let filter = self.filter()?;
+
+        let mut order_by = None;
+        if self.accept_keyword(Keyword::Order) {
+            self.expect_keyword(Keyword::By, "by")?;
+            let column = self.ident("a column name")?;
+            let order = match self.accept_keyword(Keyword::Desc) {
+                true => Order::Descending,
+                false => {
+                    self.accept_keyword(Keyword::Asc);
+                    Order::Ascending
+                },
+            };
+            order_by = Some(OrderBy { column, order });
+        }
+
+        let mut limit = None;
+        if self.accept_keyword(Keyword::Limit) {
+            limit = Some(self.value()?);
+        }
+        Ok(Stmt::Select(Select { table: Some(table), filter, order_by, limit }))
+    }
+
+    /// `insert into t [(c, ...)] values (v, ...)`. The short form is read by `short_insert`.
+    fn insert(&mut self) -> Result<Stmt, ParseError> {
+        self.expect_keyword(Keyword::Into, "into")?;
+        let table = self.ident("a table name")?;
+        let mut columns = Vec::new();
+        if self.accept(TokenKind::LeftParen) {
+            loop {
+                columns.push(self.ident("a column name")?);
+                if !self.accept(TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.expect(TokenKind::RightParen, ")")?;
+        }
+        self.expect_keyword(Keyword::Values, "values")?;
+        self.expect(TokenKind::LeftParen, "(")?;
+        let mut values = Vec::new();
+        loop {
+            values.push(self.value()?);
+            if !self.accept(TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenKind::RightParen, ")")?;
+        Ok(Stmt::Insert(Insert { table, columns, values }))
+    }
+
+    fn delete(&mut self) -> Result<Stmt, ParseError> {
+        self.expect_keyword(Keyword::From, "from")?;
+        let table = self.ident("a table name")?;
+        let filter = self.filter()?;
+        Ok(Stmt::Delete(Delete { table, filter }))
+    }
+
+    fn update(&mut self) -> Result<Stmt, ParseError> {
+        let table = self.ident("a table name")?;
+        self.expect_keyword(Keyword::Set, "set")?;
+        let mut assignments = Vec::new();
+        loop {
+            let column = self.ident("a column name")?;
+            self.expect(TokenKind::Equals, "=")?;
+            let value = self.value()?;
+            assignments.push(Assignment { column, value });
+            if !self.accept(TokenKind::Comma) {
+                break;
+            }
+        }
+        let filter = self.filter()?;
+        Ok(Stmt::Update(Update { table, assignments, filter }))
+    }
+
+    /// `create table t (c type [(size)], ...)`
+    fn create(&mut self) -> Result<Stmt, ParseError> {
+        self.expect_keyword(Keyword::Table, "table")?;
+        let table = self.ident("a table name")?;
+        self.expect(TokenKind::LeftParen, "(")?;
+        let mut columns = Vec::new();
+        loop {
+            let name = self.ident("a column name")?;
+            let type_name = self.ident("a column type")?;
+            let mut size = None;
+            if self.accept(TokenKind::LeftParen) {
+                size = Some(self.value()?);
+                self.expect(TokenKind::RightParen, ")")?;
+            }
+            columns.push(ColumnDef { name, type_name, size });
+            if !self.accept(TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenKind::RightParen, ")")?;
+        Ok(Stmt::CreateTable(CreateTable { table, columns }))
+    }
+
+    /// An optional `where c <op> v [and c <op> v ...]`.
+    fn filter(&mut self) -> Result<Vec<Condition>, ParseError> {
+        let mut conditions = Vec::new();
+        if !self.accept_keyword(Keyword::Where) {
+            return Ok(conditions);
+        }
+        loop {
+            let column = self.ident("a column name")?;
+            let op = match self.peek().kind {
+                TokenKind::Equals => CompareOp::Equals,
+                TokenKind::NotEquals => CompareOp::NotEquals,
+                TokenKind::Less => CompareOp::Less,
+                TokenKind::LessOrEqual => CompareOp::LessOrEqual,
+                TokenKind::Greater => CompareOp::Greater,
+                TokenKind::GreaterOrEqual => CompareOp::GreaterOrEqual,
+                _ => return Err(self.error("a comparison")),
+            };
+            self.advance();
+            let value = self.value()?;
+            conditions.push(Condition { column, op, value });
+            if !self.accept_keyword(Keyword::And) {
+                return Ok(conditions);
+            }
+        }
+    }
+
+    /// `pragma journal_mode = wal|delete` or `pragma wal_checkpoint`.
+    fn pragma(&mut self) -> Result<Stmt, ParseError> {
+        let name = self.ident("a pragma name")?;
+        if name.name.eq_ignore_ascii_case("wal_checkpoint") {
+            return Ok(Stmt::Pragma(Pragma::WalCheckpoint));
+        }
+        if !name.name.eq_ignore_ascii_case("journal_mode") {
+            return Err(ParseError::new(PrepareResult::PrepareSyntaxError, name.position, format!("unknown pragma '{}'", name.name)));
+        }
+        self.expect(TokenKind::Equals, "=")?;
+        let mode = self.advance();
+        match mode.kind {
+            TokenKind::Identifier(word) if word.eq_ignore_ascii_case("wal") => Ok(Stmt::Pragma(Pragma::JournalMode(JournalMode::Wal))),
+            TokenKind::Keyword(Keyword::Delete) => Ok(Stmt::Pragma(Pragma::JournalMode(JournalMode::Rollback))),
+            _ => Err(ParseError::new(PrepareResult::PrepareSyntaxError, mode.position,
+                format!("expected 'wal' or 'delete', found {}", describe(&mode)))),
+        }
+    }
+}
+
+/// The short `insert id username email` into the default table, `None` for any other
+/// statement. Values are taken as written up to whitespace, like the original command
+/// did, so they may hold any character or be keywords: only the id is read as a number.
+fn short_insert(input: &str) -> Option<Stmt> {
+    let mut words = words(input);
+    let (first, second) = (words.first()?, words.get(1));
+    if !first.text.eq_ignore_ascii_case("insert") || second.is_some_and(|word| word.text.eq_ignore_ascii_case("into")) {
+        return None;
+    }
+    let start = first.position;
+    // a semicolon may end the statement, written apart or not
+    if let Some(last) = words.last_mut() {
+        if let Some(text) = last.text.strip_suffix(';') {
+            last.text = text.to_string();
+        }
+    }
+    let values = words.into_iter().skip(1).filter(|word| !word.text.is_empty()).enumerate().map(|(i, word)| {
+        let literal = match word.text.parse::<i64>() {
+            Ok(n) if i == 0 => Literal::Integer(n),
+            _ => Literal::Text(word.text),
+        };
+        Constant { literal, position: word.position }
+    }).collect();
+    let table = Ident { name: constants::DEFAULT_TABLE_NAME.to_string(), position: start };
+    Some(Stmt::Insert(Insert { table, columns: Vec::new(), values }))
+}
+
+/// Parses a single statement, optionally ended by a semicolon.
+pub fn parse(input: &str) -> Result<Stmt, ParseError> {
+    if let Some(statement) = short_insert(input) {
+        return Ok(statement);
+    }
+    let mut parser = Parser { tokens: tokenize(input)?, next: 0 };
+    let statement = parser.statement()?;
+    parser.accept(TokenKind::Semicolon);
+    if parser.peek().kind != TokenKind::End {
+        return Err(parser.error("end of statement"));
+    }
+    Ok(statement)
+}
@@ -0,0 +1,176 @@
+use std::error::Error;
+use std::fmt;
+
+/// A value stored in a row, tagged with its own type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Integer(n) => write!(f, "{}", n),
+            // keep a decimal point, so reals read back as reals
+            Value::Real(x) if x.is_finite() && x.fract() == 0.0 => write!(f, "{:.1}", x),
+            Value::Real(x) => write!(f, "{}", x),
+            Value::Text(text) => write!(f, "{}", text),
+            Value::Blob(bytes) => {
+                write!(f, "X'")?;
+                for b in bytes {
+                    write!(f, "{:02X}", b)?;
+                }
+                write!(f, "'")
+            },
+        }
+    }
+}
+
+fn corrupt() -> Box<dyn Error> {
+    Box::new(std::io::Error::other("Malformed record. Corrupt file."))
+}
+
+/// Bytes `value` takes as a varint.
+pub fn varint_len(value: u64) -> usize {
+    match value {
+        v if v > 0x00ff_ffff_ffff_ffff => 9,
+        v => (64 - v.leading_zeros() as usize).div_ceil(7).max(1),
+    }
+}
+
+/// Appends `value` as a SQLite varint: 7 bits per byte, most significant first, the high
+/// bit set on every byte but the last. A ninth byte, if needed, holds 8 bits.
+pub fn put_varint(out: &mut Vec<u8>, value: u64) {
+    let len = varint_len(value);
+    if len == 9 {
+        out.extend((0..8).map(|i| ((value >> (57 - 7 * i)) as u8 & 0x7f) | 0x80));
+        out.push(value as u8);
+        return;
+    }
+    out.extend((0..len).rev().map(|i| {
+        let byte = (value >> (7 * i)) as u8 & 0x7f;
+        if i == 0 { byte } else { byte | 0x80 }
+    }));
+}
+
+/// Reads the varint at `offset`, moving `offset` past it.
+pub fn get_varint(bytes: &[u8], offset: &mut usize) -> Result<u64, Box<dyn Error>> {
+    let mut value = 0u64;
+    for i in 0..9 {
+        let byte = *bytes.get(*offset).ok_or_else(corrupt)?;
+        *offset += 1;
+        if i == 8 {
+            return Ok((value << 8) | byte as u64);
+        }
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    unreachable!()
+}
+
+/// Bytes of each integer serial type, 1 to 6.
+const INTEGER_SIZES: [usize; 6] = [1, 2, 3, 4, 6, 8];
+
+/// Serial type of `value`: 0 for NULL, 1 to 6 for integers of 1, 2, 3, 4, 6 and 8 bytes,
+/// 7 for a real, 8 and 9 for the integers 0 and 1 (no bytes in the body), then
+/// 12 + 2n for a blob of n bytes and 13 + 2n for text of n bytes.
+pub fn serial_type(value: &Value) -> u64 {
+    match value {
+        Value::Null => 0,
+        Value::Integer(0) => 8,
+        Value::Integer(1) => 9,
+        Value::Integer(n) => {
+            let size = INTEGER_SIZES.iter().position(|size| *size == 8 || (-1i64 << (size * 8 - 1)..1i64 << (size * 8 - 1)).contains(n));
+            size.unwrap_or(5) as u64 + 1
+        },
+        Value::Real(_) => 7,
+        Value::Blob(bytes) => 12 + 2 * bytes.len() as u64,
+        Value::Text(text) => 13 + 2 * text.len() as u64,
+    }
+}
+
+/// Bytes the body of a value of `serial_type` takes.
+fn serial_size(serial_type: u64) -> Result<usize, Box<dyn Error>> {
+    match serial_type {
+        0 | 8 | 9 => Ok(0),
+        1..=6 => Ok(INTEGER_SIZES[serial_type as usize - 1]),
+        7 => Ok(8),
+        10 | 11 => Err(corrupt()),
+        n => Ok((n as usize - 12) / 2),
+    }
+}
+
+/// Lays out `values` as a record: a header with its own size then the serial type of
+/// each value, as varints, followed by the values. Integers and reals are little-endian,
+/// like the rest of the file.
+pub fn encode_record(values: &[Value]) -> Vec<u8> {
+    let types = values.iter().map(serial_type).collect::<Vec<u64>>();
+    let types_len = types.iter().map(|t| varint_len(*t)).sum::<usize>();
+    let mut header_size = types_len + 1;
+    while varint_len(header_size as u64) + types_len != header_size {
+        header_size = types_len + varint_len(header_size as u64);
+    }
+
+    let mut record = Vec::new();
+    put_varint(&mut record, header_size as u64);
+    for t in &types {
+        put_varint(&mut record, *t);
+    }
+    for (value, t) in values.iter().zip(types) {
+        match value {
+            Value::Integer(n) => record.extend_from_slice(&n.to_le_bytes()[..serial_size(t).unwrap()]),
+            Value::Real(x) => record.extend_from_slice(&x.to_le_bytes()),
+            Value::Text(text) => record.extend_from_slice(text.as_bytes()),
+            Value::Blob(bytes) => record.extend_from_slice(bytes),
+            Value::Null => (),
+        }
+    }
+    record
+}
+
+/// Reads back the values of the record at the start of `bytes`. What follows the record
+/// is ignored.
+pub fn decode_record(bytes: &[u8]) -> Result<Vec<Value>, Box<dyn Error>> {
+    let mut offset = 0;
+    let header_size = get_varint(bytes, &mut offset)? as usize;
+    if header_size < offset || header_size > bytes.len() {
+        return Err(corrupt());
+    }
+    let mut types = Vec::new();
+    while offset < header_size {
+        types.push(get_varint(bytes, &mut offset)?);
+    }
+    if offset != header_size {
+        return Err(corrupt());
+    }
+
+    let mut values = Vec::new();
+    for t in types {
+        let size = serial_size(t)?;
+        let body = bytes.get(offset..offset + size).ok_or_else(corrupt)?;
+        offset += size;
+        values.push(match t {
+            0 => Value::Null,
+            8 => Value::Integer(0),
+            9 => Value::Integer(1),
+            1..=6 => {
+                // sign-extend from the top byte stored
+                let fill = if body[size - 1] & 0x80 != 0 { 0xff } else { 0 };
+                let mut n = [fill; 8];
+                n[..size].copy_from_slice(body);
+                Value::Integer(i64::from_le_bytes(n))
+            },
+            7 => Value::Real(f64::from_le_bytes(body.try_into()?)),
+            t if t % 2 == 0 => Value::Blob(body.to_vec()),
+            _ => Value::Text(String::from_utf8(body.to_vec())?),
+        });
+    }
+    Ok(values)
+}
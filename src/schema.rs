@@ -0,0 +1,151 @@
+use std::error::Error;
+
+use crate::ast::{Constant, CreateTable, Literal, Stmt};
+use crate::constants;
+use crate::lexer::Position;
+use crate::parser::{parse, ParseError};
+use crate::record::Value;
+use crate::sql::PrepareResult;
+use crate::table::Row;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text(u32), // at most this many bytes
+    Blob(u32), // at most this many bytes
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub column_type: ColumnType,
+}
+
+/// Name and columns of a table. The first column is an integer, the key the rows are
+/// sorted by in the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schema {
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub sql: String, // the create table statement, as written
+}
+
+fn schema_error(position: Position, message: impl Into<String>) -> ParseError {
+    ParseError::new(PrepareResult::PrepareSyntaxError, position, message)
+}
+
+fn size_of(type_name: &str, size: &Constant) -> Result<u32, ParseError> {
+    match size.literal {
+        Literal::Integer(n) if n > 0 && n <= constants::MAX_VALUE_SIZE as i64 => Ok(n as u32),
+        _ => Err(schema_error(size.position, format!("{} size must be between 1 and {}", type_name, constants::MAX_VALUE_SIZE))),
+    }
+}
+
+impl Schema {
+    /// The table a new database starts with, see `constants::DEFAULT_TABLE_SQL`.
+    pub fn users() -> Schema {
+        let column = |name: &str, column_type| Column { name: name.to_string(), column_type };
+        Schema {
+            name: constants::DEFAULT_TABLE_NAME.to_string(),
+            columns: vec![
+                column("id", ColumnType::Integer),
+                column("username", ColumnType::Text(constants::USERNAME_SIZE)),
+                column("email", ColumnType::Text(constants::EMAIL_SIZE)),
+            ],
+            sql: constants::DEFAULT_TABLE_SQL.to_string(),
+        }
+    }
+
+    /// The system catalog, see `constants::CATALOG_TABLE_SQL`.
+    pub fn catalog() -> Schema {
+        let column = |name: &str, column_type| Column { name: name.to_string(), column_type };
+        Schema {
+            name: constants::CATALOG_TABLE_NAME.to_string(),
+            columns: vec![
+                column("id", ColumnType::Integer),
+                column("type", ColumnType::Text(constants::CATALOG_TYPE_SIZE)),
+                column("name", ColumnType::Text(constants::CATALOG_NAME_SIZE)),
+                column("rootpage", ColumnType::Integer),
+                column("sql", ColumnType::Text(constants::CATALOG_SQL_SIZE)),
+            ],
+            sql: constants::CATALOG_TABLE_SQL.to_string(),
+        }
+    }
+
+    /// Reads back the schema a `create table` statement defines.
+    pub fn from_sql(sql: &str) -> Result<Schema, ParseError> {
+        match parse(sql)? {
+            Stmt::CreateTable(create) => Schema::from_create(&create, sql),
+            _ => Err(schema_error(Position { line: 1, column: 1 }, "expected a create table statement")),
+        }
+    }
+
+    /// Checks the columns of `create`: known types, unique names and an integer key first.
+    pub fn from_create(create: &CreateTable, sql: &str) -> Result<Schema, ParseError> {
+        let mut columns: Vec<Column> = Vec::new();
+        for def in &create.columns {
+            if columns.iter().any(|column| column.name == def.name.name) {
+                return Err(schema_error(def.name.position, format!("duplicate column name: {}", def.name.name)));
+            }
+            let column_type = match def.type_name.name.to_ascii_lowercase().as_str() {
+                "integer" | "int" => match &def.size {
+                    Some(size) => return Err(schema_error(size.position, "integer columns take no size")),
+                    None => ColumnType::Integer,
+                },
+                "real" | "float" | "double" => match &def.size {
+                    Some(size) => return Err(schema_error(size.position, "real columns take no size")),
+                    None => ColumnType::Real,
+                },
+                "text" | "varchar" | "char" => match &def.size {
+                    Some(size) => ColumnType::Text(size_of("text", size)?),
+                    None => ColumnType::Text(constants::DEFAULT_TEXT_SIZE),
+                },
+                "blob" => match &def.size {
+                    Some(size) => ColumnType::Blob(size_of("blob", size)?),
+                    None => ColumnType::Blob(constants::DEFAULT_TEXT_SIZE),
+                },
+                _ => return Err(schema_error(def.type_name.position, format!("unknown column type: {}", def.type_name.name))),
+            };
+            columns.push(Column { name: def.name.name.clone(), column_type });
+        }
+        if columns[0].column_type != ColumnType::Integer {
+            return Err(schema_error(create.columns[0].type_name.position, "the first column is the key and must be an integer"));
+        }
+
+        Ok(Schema { name: create.table.name.clone(), columns, sql: sql.to_string() })
+    }
+
+    pub fn key(&self) -> &Column {
+        &self.columns[0]
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|column| column.name == name)
+    }
+
+    /// Checks the values of `row` against the columns, filling the ones it has no value for
+    /// with NULL. Integers stored in a real column become reals.
+    pub fn conform(&self, row: &mut Row) -> Result<(), Box<dyn Error>> {
+        if row.fields.len() >= self.columns.len() {
+            return Err(Box::new(std::io::Error::other(format!(
+                "Row has {} values for the {} columns of {}", row.fields.len() + 1, self.columns.len(), self.name))));
+        }
+        row.fields.resize(self.columns.len() - 1, Value::Null);
+        for (column, value) in self.columns[1..].iter().zip(row.fields.iter_mut()) {
+            match (column.column_type, &*value) {
+                (_, Value::Null) | (ColumnType::Integer, Value::Integer(_)) | (ColumnType::Real, Value::Real(_)) => (),
+                (ColumnType::Real, Value::Integer(n)) => *value = Value::Real(*n as f64),
+                (ColumnType::Text(size), Value::Text(text)) if text.len() > size as usize => {
+                    return Err(Box::new(std::io::Error::other(format!("Text for {} is longer than {} bytes", column.name, size))));
+                },
+                (ColumnType::Blob(size), Value::Blob(bytes)) if bytes.len() > size as usize => {
+                    return Err(Box::new(std::io::Error::other(format!("Blob for {} is longer than {} bytes", column.name, size))));
+                },
+                (ColumnType::Text(_), Value::Text(_)) | (ColumnType::Blob(_), Value::Blob(_)) => (),
+                (_, value) => return Err(Box::new(std::io::Error::other(format!("Wrong type of value for {}: {}", column.name, value)))),
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,12 @@
-use crate::table::{insert_row, select_all_rows, Row, Table};
+use std::error::Error;
+use std::ops::Bound;
+
+use crate::ast::{CompareOp, Condition, Constant, Ident, Insert, Literal, Pragma, Select, Stmt};
+use crate::parser::{parse, ParseError};
+use crate::record::Value;
+use crate::schema::{Column, ColumnType, Schema};
+use crate::constants;
+use crate::table::{delete_rows, insert_row, select_rows, update_rows, Database, JournalMode, Order, Row, RowUpdate, Table};
 
 
 #[derive(Debug)]
@@ -7,14 +15,37 @@ pub enum MetaCommandResult {
     MetaCommandUnrecognizedCommand(String),
 }
 
+/// Range of ids selected by a `where` clause.
+pub type IdRange = (Bound<u32>, Bound<u32>);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelectQuery {
+    pub range: IdRange,
+    pub order: Order,
+    pub limit: Option<u32>,
+}
+
 #[derive(Debug, Clone)]
 pub enum StatementType {
     Insert,
     Select,
+    SelectQuery(SelectQuery),
+    Delete(IdRange),
+    Update(Box<RowUpdate>, IdRange),
+    CreateTable(Schema),
+    Vacuum,
+    JournalMode(JournalMode),
+    WalCheckpoint,
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint(String),
+    Release(String),
+    RollbackTo(String),
     Invalid(String)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PrepareResult{
     PrepareSuccess,
     PrepareSyntaxError,
@@ -33,73 +64,292 @@ pub enum ExecuteResult {
 #[derive(Debug, Clone)]
 pub struct Statement {
     pub statement_type: StatementType,
-    pub row_to_insert: Row
+    pub row_to_insert: Row,
+    pub table: String, // table the statement reads or writes, empty for the others
 }
 
 
 
+/// Parses `cmd` into `statement`, for a database holding only the built-in users table.
+/// Errors tell where in `cmd` the problem is.
+pub fn prepare(cmd: &str, statement: &mut Statement) -> Result<(), ParseError> {
+    prepare_for(&[Table { root_page_num: constants::ROOT_PAGE_NUM, schema: Schema::users() }], cmd, statement)
+}
+
+/// Same as `prepare`, checking table and column names against `tables`.
+pub fn prepare_for(tables: &[Table], cmd: &str, statement: &mut Statement) -> Result<(), ParseError> {
+    statement.table = String::new();
+    statement.statement_type = match parse(cmd)? {
+        Stmt::Insert(insert) => {
+            let schema = find_table(tables, &insert.table)?;
+            statement.row_to_insert = insert_values(schema, &insert)?;
+            statement.table = schema.name.clone();
+            StatementType::Insert
+        },
+        Stmt::Select(Select { table: None, .. }) => {
+            statement.table = constants::DEFAULT_TABLE_NAME.to_string();
+            StatementType::Select
+        },
+        Stmt::Select(Select { table: Some(table), filter, order_by, limit }) => {
+            let schema = find_table(tables, &table)?;
+            statement.table = schema.name.clone();
+            let order = match order_by {
+                Some(order_by) => {
+                    check_key_column(schema, &order_by.column, "order by")?;
+                    order_by.order
+                },
+                None => Order::Ascending,
+            };
+            let limit = match limit {
+                Some(value) => match value.literal {
+                    Literal::Integer(n) if u32::try_from(n).is_ok() => Some(n as u32),
+                    _ => return Err(ParseError::new(PrepareResult::PrepareSyntaxError, value.position, "limit must be a non-negative integer")),
+                },
+                None => None,
+            };
+            StatementType::SelectQuery(SelectQuery { range: id_range(schema, &filter)?, order, limit })
+        },
+        Stmt::Delete(delete) => {
+            let schema = find_table(tables, &delete.table)?;
+            statement.table = schema.name.clone();
+            StatementType::Delete(id_range(schema, &delete.filter)?)
+        },
+        Stmt::Update(update) => {
+            let schema = find_table(tables, &update.table)?;
+            statement.table = schema.name.clone();
+            let mut row_update = RowUpdate::default();
+            for assignment in &update.assignments {
+                let value = &assignment.value;
+                match column_index(schema, &assignment.column)? {
+                    0 => row_update.id = Some(parse_key(schema, value)?),
+                    i => row_update.fields.push((i - 1, to_value(&schema.columns[i], value)?)),
+                }
+            }
+            StatementType::Update(Box::new(row_update), id_range(schema, &update.filter)?)
+        },
+        Stmt::CreateTable(create) => StatementType::CreateTable(Schema::from_create(&create, cmd.trim().trim_end_matches(';').trim_end())?),
+        Stmt::Vacuum => StatementType::Vacuum,
+        Stmt::Begin => StatementType::Begin,
+        Stmt::Commit => StatementType::Commit,
+        Stmt::Rollback => StatementType::Rollback,
+        Stmt::Savepoint(name) => StatementType::Savepoint(name.name),
+        Stmt::Release(name) => StatementType::Release(name.name),
+        Stmt::RollbackTo(name) => StatementType::RollbackTo(name.name),
+        Stmt::Pragma(Pragma::JournalMode(mode)) => StatementType::JournalMode(mode),
+        Stmt::Pragma(Pragma::WalCheckpoint) => StatementType::WalCheckpoint,
+    };
+    Ok(())
+}
+
+/// Same as `prepare`, reporting only the kind of error.
 pub fn prepare_statement(cmd: &str, statement: &mut Statement) -> PrepareResult {
-    if cmd.starts_with("insert") {
-        statement.statement_type = StatementType::Insert;
-        let split_info = cmd.split_whitespace().collect::<Vec<&str>>();
+    match prepare(cmd, statement) {
+        Ok(()) => PrepareResult::PrepareSuccess,
+        Err(err) => err.result,
+    }
+}
 
-        if split_info.len() != 4 {
-            return PrepareResult::PrepareSyntaxError; 
-        }
+fn find_table<'a>(tables: &'a [Table], table: &Ident) -> Result<&'a Schema, ParseError> {
+    match tables.iter().find(|t| t.schema.name == table.name) {
+        Some(t) => Ok(&t.schema),
+        None => Err(ParseError::new(PrepareResult::PrepareSyntaxError, table.position, format!("no such table: {}", table.name))),
+    }
+}
 
-        statement.row_to_insert.id = match split_info[1].parse::<i32>() {
-            Ok(id) => {
-                if id < 0 {
-                    return PrepareResult::PrepareNegativeID;
-                }
-                u32::try_from(id).unwrap()
-            },
-            Err(_) => return PrepareResult::PrepareSyntaxError,
-        };
+fn no_such_column(column: &Ident) -> ParseError {
+    ParseError::new(PrepareResult::PrepareSyntaxError, column.position, format!("no such column: {}", column.name))
+}
 
-        match split_info[2].as_bytes().get(..split_info[2].len()) {
-            Some(username_bytes) => {
-                if username_bytes.len() > 32 {
-                    return PrepareResult::PrepareStringTooLong;
-                }
-                statement.row_to_insert.username[..split_info[2].len()].copy_from_slice(username_bytes);
-            },
-            None => return PrepareResult::PrepareSyntaxError,
-        }
+fn column_index(schema: &Schema, column: &Ident) -> Result<usize, ParseError> {
+    schema.column_index(&column.name).ok_or_else(|| no_such_column(column))
+}
 
-        match split_info[3].as_bytes().get(..split_info[3].len()) {
-            Some(email_bytes) => {
-                if email_bytes.len() > 255 {
-                    return PrepareResult::PrepareStringTooLong;
+/// Checks that `column`, named in `clause`, is the key: rows are only found by their key.
+fn check_key_column(schema: &Schema, column: &Ident, clause: &str) -> Result<(), ParseError> {
+    column_index(schema, column)?;
+    if column.name != schema.key().name {
+        return Err(ParseError::new(PrepareResult::PrepareSyntaxError, column.position,
+            format!("only the key column {} can be used in {}, not {}", schema.key().name, clause, column.name)));
+    }
+    Ok(())
+}
 
-                }
-                statement.row_to_insert.email[..split_info[3].len()].copy_from_slice(email_bytes);
+/// The row described by an insert, whose values are either listed for every column in
+/// order or matched to the named columns. Columns left out are NULL.
+fn insert_values(schema: &Schema, insert: &Insert) -> Result<Row, ParseError> {
+    let columns = match insert.columns.is_empty() {
+        true => (0..schema.columns.len()).collect(),
+        false => insert.columns.iter().map(|column| column_index(schema, column)).collect::<Result<Vec<usize>, ParseError>>()?,
+    };
+    if columns.len() != insert.values.len() {
+        return Err(ParseError::new(PrepareResult::PrepareSyntaxError, insert.table.position,
+            format!("{} values for {} columns", insert.values.len(), columns.len())));
+    }
+
+    let mut row = Row::new();
+    row.fields = vec![Value::Null; schema.columns.len() - 1];
+    let mut has_key = false;
+    for (value, i) in insert.values.iter().zip(columns) {
+        match i {
+            0 => {
+                row.id = parse_key(schema, value)?;
+                has_key = true;
             },
-            None => return PrepareResult::PrepareSyntaxError,
+            i => row.fields[i - 1] = to_value(&schema.columns[i], value)?,
         }
-        PrepareResult::PrepareSuccess
-    } else if cmd.starts_with("select") {
-        statement.statement_type = StatementType::Select;
-        PrepareResult::PrepareSuccess
-    } else {
-        PrepareResult::PrepareUnrecognizedStatement
+    }
+    if !has_key {
+        return Err(ParseError::new(PrepareResult::PrepareSyntaxError, insert.table.position,
+            format!("a value for {} is required", schema.key().name)));
+    }
+    Ok(row)
+}
+
+fn parse_key(schema: &Schema, value: &Constant) -> Result<u32, ParseError> {
+    let name = &schema.key().name;
+    match value.literal {
+        Literal::Integer(id) if id < 0 => Err(ParseError::new(PrepareResult::PrepareNegativeID, value.position, format!("{} must be positive", name))),
+        Literal::Integer(id) => u32::try_from(id)
+            .map_err(|_| ParseError::new(PrepareResult::PrepareSyntaxError, value.position, format!("{} is too large", name))),
+        _ => Err(ParseError::new(PrepareResult::PrepareSyntaxError, value.position, format!("{} must be an integer", name))),
+    }
+}
+
+/// `value` as stored in `column`. Any column takes NULL, numbers are accepted as text
+/// and integers as reals, and text is accepted as a blob of its bytes.
+fn to_value(column: &Column, value: &Constant) -> Result<Value, ParseError> {
+    let wrong_type = |expected: &str| ParseError::new(PrepareResult::PrepareSyntaxError, value.position,
+        format!("{} must be {}", column.name, expected));
+    match (column.column_type, &value.literal) {
+        (_, Literal::Null) => Ok(Value::Null),
+        (ColumnType::Integer, Literal::Integer(n)) => Ok(Value::Integer(*n)),
+        (ColumnType::Integer, _) => Err(wrong_type("an integer")),
+        (ColumnType::Real, Literal::Integer(n)) => Ok(Value::Real(*n as f64)),
+        (ColumnType::Real, Literal::Real(x)) => Ok(Value::Real(*x)),
+        (ColumnType::Real, _) => Err(wrong_type("a number")),
+        (ColumnType::Text(size), literal) => {
+            let text = match literal {
+                Literal::Text(text) => text.clone(),
+                Literal::Integer(n) => n.to_string(),
+                Literal::Real(x) => x.to_string(),
+                _ => return Err(wrong_type("text")),
+            };
+            if text.len() > size as usize {
+                return Err(ParseError::new(PrepareResult::PrepareStringTooLong, value.position, format!("text is longer than {} bytes", size)));
+            }
+            Ok(Value::Text(text))
+        },
+        (ColumnType::Blob(size), literal) => {
+            let bytes = match literal {
+                Literal::Blob(bytes) => bytes.clone(),
+                Literal::Text(text) => text.as_bytes().to_vec(),
+                _ => return Err(wrong_type("a blob")),
+            };
+            if bytes.len() > size as usize {
+                return Err(ParseError::new(PrepareResult::PrepareStringTooLong, value.position, format!("blob is longer than {} bytes", size)));
+            }
+            Ok(Value::Blob(bytes))
+        },
+    }
+}
+
+/// Ids allowed by every `key <op> N` condition of a `where` clause.
+fn id_range(schema: &Schema, filter: &[Condition]) -> Result<IdRange, ParseError> {
+    let mut range: IdRange = (Bound::Unbounded, Bound::Unbounded);
+    for condition in filter {
+        check_key_column(schema, &condition.column, "a where clause")?;
+        let value = parse_key(schema, &condition.value)?;
+        let bounds = match condition.op {
+            CompareOp::Equals => (Bound::Included(value), Bound::Included(value)),
+            CompareOp::Less => (Bound::Unbounded, Bound::Excluded(value)),
+            CompareOp::LessOrEqual => (Bound::Unbounded, Bound::Included(value)),
+            CompareOp::Greater => (Bound::Excluded(value), Bound::Unbounded),
+            CompareOp::GreaterOrEqual => (Bound::Included(value), Bound::Unbounded),
+            CompareOp::NotEquals => return Err(ParseError::new(PrepareResult::PrepareSyntaxError, condition.column.position,
+                format!("only =, <, <=, > and >= are supported on {}", schema.key().name))),
+        };
+        range = (tighter_start(range.0, bounds.0), tighter_end(range.1, bounds.1));
+    }
+    Ok(range)
+}
+
+fn tighter_start(a: Bound<u32>, b: Bound<u32>) -> Bound<u32> {
+    match (a, b) {
+        (Bound::Unbounded, x) | (x, Bound::Unbounded) => x,
+        (Bound::Included(x), Bound::Included(y)) => Bound::Included(x.max(y)),
+        (Bound::Excluded(x), Bound::Excluded(y)) => Bound::Excluded(x.max(y)),
+        (Bound::Included(i), Bound::Excluded(e)) | (Bound::Excluded(e), Bound::Included(i)) => {
+            if i > e { Bound::Included(i) } else { Bound::Excluded(e) }
+        },
+    }
+}
+
+fn tighter_end(a: Bound<u32>, b: Bound<u32>) -> Bound<u32> {
+    match (a, b) {
+        (Bound::Unbounded, x) | (x, Bound::Unbounded) => x,
+        (Bound::Included(x), Bound::Included(y)) => Bound::Included(x.min(y)),
+        (Bound::Excluded(x), Bound::Excluded(y)) => Bound::Excluded(x.min(y)),
+        (Bound::Included(i), Bound::Excluded(e)) | (Bound::Excluded(e), Bound::Included(i)) => {
+            if i < e { Bound::Included(i) } else { Bound::Excluded(e) }
+        },
+    }
+}
+
+pub fn execute_statement(statement: Statement, db: &mut Database) -> ExecuteResult {
+    let writes = matches!(statement.statement_type, StatementType::Insert | StatementType::Delete(_) | StatementType::Update(..) | StatementType::CreateTable(_));
+    if !writes || db.pager.in_transaction() {
+        return execute_statement_type(statement, db);
+    }
+
+    // autocommit: a bare statement runs in a transaction of its own,
+    // so a statement failing halfway leaves no change behind
+    if let Err(err) = db.pager.begin() {
+        println!("Execute error");
+        return ExecuteResult::ExecuteFailure(err.to_string());
+    }
+    let result = execute_statement_type(statement, db);
+    let end = match result {
+        ExecuteResult::ExecuteSuccess => db.pager.commit(),
+        ExecuteResult::ExecuteFailure(_) => db.rollback(),
+    };
+    match end {
+        Err(err) if result == ExecuteResult::ExecuteSuccess => {
+            println!("Execute error");
+            ExecuteResult::ExecuteFailure(err.to_string())
+        },
+        _ => result,
     }
 }
 
-pub fn execute_statement(statement: Statement, table: &mut Table) -> ExecuteResult {
+fn execute_statement_type(statement: Statement, db: &mut Database) -> ExecuteResult {
+    let table = statement.table.as_str();
     match &statement.statement_type {
-        StatementType::Insert => execute_insert(statement, table),
-        StatementType::Select => execute_select(table),
+        StatementType::Insert => execute_insert(statement, db),
+        StatementType::Select => execute_select(table, SelectQuery { range: (Bound::Unbounded, Bound::Unbounded), order: Order::Ascending, limit: None }, db),
+        StatementType::SelectQuery(query) => execute_select(table, *query, db),
+        StatementType::Delete(range) => execute_delete(table, *range, db),
+        StatementType::Update(update, range) => execute_update(table, update, *range, db),
+        StatementType::CreateTable(schema) => execute_transaction(db.create_table(schema.clone())),
+        StatementType::Vacuum => execute_vacuum(db),
+        StatementType::JournalMode(mode) => execute_journal_mode(*mode, db),
+        StatementType::WalCheckpoint => execute_wal_checkpoint(db),
+        StatementType::Begin => execute_transaction(db.pager.begin()),
+        StatementType::Commit => execute_transaction(db.pager.commit()),
+        StatementType::Rollback => execute_transaction(db.rollback()),
+        StatementType::Savepoint(name) => execute_transaction(db.pager.savepoint(name)),
+        StatementType::Release(name) => execute_transaction(db.pager.release(name)),
+        StatementType::RollbackTo(name) => execute_transaction(db.rollback_to(name)),
         _ => panic!("Invalid statement type: {:?}", statement.statement_type) 
     }
 }
 
-pub fn execute_insert(statement: Statement, table: &mut Table)  -> ExecuteResult {
+pub fn execute_insert(statement: Statement, db: &mut Database)  -> ExecuteResult {
     let row = statement.row_to_insert;
+    let id = row.id;
 
-    match insert_row(table, row) {
+    match insert_row(db, &statement.table, row) {
         Ok(_) => {
-            println!("Inserted row with id: {}", row.id);
+            println!("Inserted row with id: {}", id);
             ExecuteResult::ExecuteSuccess
         }, 
         Err(err) => {
@@ -111,15 +361,85 @@ pub fn execute_insert(statement: Statement, table: &mut Table)  -> ExecuteResult
 
 }
 
-pub fn execute_select(table: &mut Table) -> ExecuteResult {
-    match select_all_rows(table) {
+pub fn execute_delete(table: &str, range: IdRange, db: &mut Database) -> ExecuteResult {
+    match delete_rows(db, table, range) {
+        Ok(deleted) => {
+            println!("Deleted {} rows", deleted);
+            ExecuteResult::ExecuteSuccess
+        },
+        Err(err) => {
+            println!("Execute error");
+            ExecuteResult::ExecuteFailure(err.to_string())
+        }
+    }
+}
+
+pub fn execute_update(table: &str, update: &RowUpdate, range: IdRange, db: &mut Database) -> ExecuteResult {
+    match update_rows(db, table, range, update) {
+        Ok(updated) => {
+            println!("Updated {} rows", updated);
+            ExecuteResult::ExecuteSuccess
+        },
+        Err(err) => {
+            println!("Execute error");
+            ExecuteResult::ExecuteFailure(err.to_string())
+        }
+    }
+}
+
+pub fn execute_vacuum(db: &mut Database) -> ExecuteResult {
+    match db.vacuum() {
+        Ok(reclaimed) => {
+            println!("Vacuum reclaimed {} bytes", reclaimed);
+            ExecuteResult::ExecuteSuccess
+        },
+        Err(err) => {
+            println!("Execute error");
+            ExecuteResult::ExecuteFailure(err.to_string())
+        }
+    }
+}
+
+pub fn execute_select(table: &str, query: SelectQuery, db: &mut Database) -> ExecuteResult {
+    match select_rows(db, table, query.range, query.order, query.limit) {
         Ok(res) => {
             for row in res {
-                println!("({}, {}, {})", row.id, std::str::from_utf8(&row.username).unwrap(), std::str::from_utf8(&row.email).unwrap());
+                let fields = row.fields.iter().map(|value| value.to_string()).collect::<Vec<String>>();
+                println!("({}, {})", row.id, fields.join(", "));
             }
             ExecuteResult::ExecuteSuccess
         }, 
         Err(_) => ExecuteResult::ExecuteFailure("Error selecting rows".to_string()),
     }
     
-}
\ No newline at end of file
+}
+
+pub fn execute_journal_mode(mode: JournalMode, db: &mut Database) -> ExecuteResult {
+    match db.pager.set_journal_mode(mode) {
+        Ok(_) => ExecuteResult::ExecuteSuccess,
+        Err(err) => {
+            println!("Execute error");
+            ExecuteResult::ExecuteFailure(err.to_string())
+        }
+    }
+}
+
+pub fn execute_wal_checkpoint(db: &mut Database) -> ExecuteResult {
+    match db.pager.wal_checkpoint() {
+        Ok(_) => ExecuteResult::ExecuteSuccess,
+        Err(err) => {
+            println!("Execute error");
+            ExecuteResult::ExecuteFailure(err.to_string())
+        }
+    }
+}
+
+pub fn execute_transaction(result: Result<(), Box<dyn Error>>) -> ExecuteResult {
+    match result {
+        Ok(_) => ExecuteResult::ExecuteSuccess,
+        Err(err) => {
+            println!("Execute error");
+            ExecuteResult::ExecuteFailure(err.to_string())
+        }
+    }
+}
@@ -0,0 +1,477 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use memmap2::Mmap;
+use rand::Rng;
+
+use crate::constants;
+
+/// A file the pager, the journal and the log read and write through. Writes are only
+/// durable once `sync` returns.
+pub trait Storage {
+    /// Fills `buf` from `offset`, failing if the file ends first.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Box<dyn Error>>;
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), Box<dyn Error>>;
+    fn size(&mut self) -> Result<u64, Box<dyn Error>>;
+    fn set_size(&mut self, size: u64) -> Result<(), Box<dyn Error>>;
+    fn sync(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// The whole file, if it is memory-mapped, so it can be read without copying.
+    fn mapped(&mut self) -> Result<Option<&[u8]>, Box<dyn Error>> {
+        Ok(None)
+    }
+}
+
+/// The set of files a database lives in: the database file itself, plus its journal or log.
+/// Creating, removing and renaming files is durable as soon as it returns.
+pub trait Vfs {
+    /// Opens the file at `path`, creating it empty if it does not exist.
+    fn open(&self, path: &Path) -> Result<Box<dyn Storage>, Box<dyn Error>>;
+    fn exists(&self, path: &Path) -> bool;
+    fn remove(&self, path: &Path) -> Result<(), Box<dyn Error>>;
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), Box<dyn Error>>;
+}
+
+/// Files on the local file system.
+pub struct OsVfs;
+
+pub struct OsFile {
+    file: File,
+}
+
+impl Storage for OsFile {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(buf)?;
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(data)?;
+        Ok(())
+    }
+
+    fn size(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn set_size(&mut self, size: u64) -> Result<(), Box<dyn Error>> {
+        self.file.set_len(size)?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Syncs the directory holding `path`, which makes the creation, removal or renaming of
+/// the file durable, not just its content.
+fn sync_dir(path: &Path) -> Result<(), Box<dyn Error>> {
+    if cfg!(unix) {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+impl Vfs for OsVfs {
+    fn open(&self, path: &Path) -> Result<Box<dyn Storage>, Box<dyn Error>> {
+        let created = !path.exists();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if created {
+            sync_dir(path)?;
+        }
+        Ok(Box::new(OsFile { file }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn remove(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::remove_file(path)?;
+        sync_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+        std::fs::rename(from, to)?;
+        sync_dir(to)
+    }
+}
+
+/// Files on the local file system, read through a memory map instead of a copy per read.
+/// Writes still go through the file, so they show up in the map and are synced as usual.
+/// The file must not be truncated by another process while it is open.
+pub struct MmapVfs;
+
+pub struct MmapFile {
+    file: OsFile,
+    map: Option<Mmap>, // dropped whenever the file changes size, mapped again on the next read
+}
+
+impl MmapFile {
+    /// The whole file, mapped if it was not already.
+    fn map(&mut self) -> Result<&[u8], Box<dyn Error>> {
+        if self.map.is_none() && self.file.size()? > 0 {
+            // SAFETY: the file only changes through this handle, which drops the map before
+            // it shrinks, so the mapped bytes stay valid
+            self.map = Some(unsafe { Mmap::map(&self.file.file)? });
+        }
+        Ok(self.map.as_deref().unwrap_or(&[]))
+    }
+}
+
+impl Storage for MmapFile {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        let start = offset as usize;
+        let map = self.map()?;
+        if map.len() < start + buf.len() {
+            return Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+        }
+        buf.copy_from_slice(&map[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.map.as_ref().is_some_and(|map| map.len() < offset as usize + data.len()) {
+            self.map = None;
+        }
+        self.file.write_at(offset, data)
+    }
+
+    fn size(&mut self) -> Result<u64, Box<dyn Error>> {
+        self.file.size()
+    }
+
+    fn set_size(&mut self, size: u64) -> Result<(), Box<dyn Error>> {
+        self.map = None;
+        self.file.set_size(size)
+    }
+
+    fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        self.file.sync()
+    }
+
+    fn mapped(&mut self) -> Result<Option<&[u8]>, Box<dyn Error>> {
+        self.map().map(Some)
+    }
+}
+
+impl Vfs for MmapVfs {
+    fn open(&self, path: &Path) -> Result<Box<dyn Storage>, Box<dyn Error>> {
+        let created = !path.exists();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if created {
+            sync_dir(path)?;
+        }
+        Ok(Box::new(MmapFile { file: OsFile { file }, map: None }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        OsVfs.exists(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        OsVfs.remove(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+        OsVfs.rename(from, to)
+    }
+}
+
+/// Files kept in memory, gone once the last clone is dropped. Backs `:memory:` databases:
+/// there is no power loss to survive, so a file is just its bytes and syncing does nothing.
+#[derive(Clone, Default)]
+pub struct RamVfs {
+    files: Rc<RefCell<HashMap<PathBuf, RamData>>>,
+}
+
+type RamData = Rc<RefCell<Vec<u8>>>;
+
+pub struct RamFile {
+    data: RamData,
+}
+
+impl RamVfs {
+    pub fn new() -> RamVfs {
+        RamVfs::default()
+    }
+}
+
+impl Storage for RamFile {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        let data = self.data.borrow();
+        let start = offset as usize;
+        if data.len() < start + buf.len() {
+            return Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+        }
+        buf.copy_from_slice(&data[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        apply(&mut self.data.borrow_mut(), &Unsynced::Write { offset, data: data.to_vec() });
+        Ok(())
+    }
+
+    fn size(&mut self) -> Result<u64, Box<dyn Error>> {
+        Ok(self.data.borrow().len() as u64)
+    }
+
+    fn set_size(&mut self, size: u64) -> Result<(), Box<dyn Error>> {
+        self.data.borrow_mut().resize(size as usize, 0);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+impl Vfs for RamVfs {
+    fn open(&self, path: &Path) -> Result<Box<dyn Storage>, Box<dyn Error>> {
+        let data = self.files.borrow_mut().entry(path.to_path_buf()).or_default().clone();
+        Ok(Box::new(RamFile { data }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.borrow().contains_key(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        match self.files.borrow_mut().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound))),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+        let mut files = self.files.borrow_mut();
+        match files.remove(from) {
+            Some(file) => {
+                files.insert(to.to_path_buf(), file);
+                Ok(())
+            },
+            None => Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound))),
+        }
+    }
+}
+
+/// A change made to a file since it was last synced.
+#[derive(Clone)]
+enum Unsynced {
+    Write { offset: u64, data: Vec<u8> },
+    SetSize(u64),
+}
+
+#[derive(Default)]
+struct MemFileData {
+    durable: Vec<u8>, // what survives a crash
+    unsynced: Vec<Unsynced>, // in the order they were made
+    current: Vec<u8>, // `durable` with every unsynced change applied
+}
+
+#[derive(Default)]
+struct MemState {
+    files: HashMap<PathBuf, Rc<RefCell<MemFileData>>>,
+    writes: u64,
+    fail_at_write: Option<u64>,
+    crashed: bool, // the injected failure happened: every operation fails until `crash`
+}
+
+impl MemState {
+    fn check_alive(&self) -> Result<(), Box<dyn Error>> {
+        if self.crashed {
+            return Err(Box::new(std::io::Error::other("Simulated crash")));
+        }
+        Ok(())
+    }
+}
+
+/// Files kept in memory for tests. It keeps track of what was synced, so `crash` can throw
+/// away (or tear) the writes a power loss could lose, and it can fail the Nth write to stop
+/// the "process" at an arbitrary point. Clones share the same files.
+#[derive(Clone, Default)]
+pub struct MemVfs {
+    state: Rc<RefCell<MemState>>,
+}
+
+pub struct MemFile {
+    state: Rc<RefCell<MemState>>,
+    data: Rc<RefCell<MemFileData>>,
+}
+
+fn apply(content: &mut Vec<u8>, change: &Unsynced) {
+    match change {
+        Unsynced::Write { offset, data } => {
+            let offset = *offset as usize;
+            if content.len() < offset + data.len() {
+                content.resize(offset + data.len(), 0);
+            }
+            content[offset..offset + data.len()].copy_from_slice(data);
+        },
+        Unsynced::SetSize(size) => content.resize(*size as usize, 0),
+    }
+}
+
+impl MemVfs {
+    pub fn new() -> MemVfs {
+        MemVfs::default()
+    }
+
+    /// Number of writes made so far, across every file.
+    pub fn writes(&self) -> u64 {
+        self.state.borrow().writes
+    }
+
+    /// Makes write number `n` (counting from 1, across every file, see `writes`) fail
+    /// without being applied, along with every operation after it, as if the process
+    /// died right there.
+    pub fn fail_at_write(&self, n: u64) {
+        self.state.borrow_mut().fail_at_write = Some(n);
+    }
+
+    /// Whether the failure set by `fail_at_write` happened.
+    pub fn has_crashed(&self) -> bool {
+        self.state.borrow().crashed
+    }
+
+    /// Simulates a power loss: every change not synced yet is independently kept, lost, or,
+    /// for writes, torn at a sector boundary. Files work again afterwards, holding what a
+    /// restarted process would find on disk.
+    pub fn crash(&self, rng: &mut impl Rng) {
+        let mut state = self.state.borrow_mut();
+        for file in state.files.values() {
+            let mut file = file.borrow_mut();
+            let mut content = file.durable.clone();
+            for change in &file.unsynced {
+                match (rng.gen_range(0..3), change) {
+                    (0, _) => apply(&mut content, change),
+                    (1, Unsynced::Write { offset, data }) if data.len() > constants::SECTOR_SIZE as usize => {
+                        let sectors = data.len().div_ceil(constants::SECTOR_SIZE as usize);
+                        let kept = rng.gen_range(1..sectors) * constants::SECTOR_SIZE as usize;
+                        apply(&mut content, &Unsynced::Write { offset: *offset, data: data[..kept].to_vec() });
+                    },
+                    _ => (),
+                }
+            }
+            file.durable = content.clone();
+            file.current = content;
+            file.unsynced.clear();
+        }
+        state.fail_at_write = None;
+        state.crashed = false;
+    }
+
+    /// Content of the file at `path` as the database sees it, unsynced writes included.
+    pub fn read(&self, path: &Path) -> Option<Vec<u8>> {
+        self.state.borrow().files.get(path).map(|file| file.borrow().current.clone())
+    }
+}
+
+impl Storage for MemFile {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), Box<dyn Error>> {
+        self.state.borrow().check_alive()?;
+        let data = self.data.borrow();
+        let start = offset as usize;
+        if data.current.len() < start + buf.len() {
+            return Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+        }
+        buf.copy_from_slice(&data.current[start..start + buf.len()]);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state.borrow_mut();
+        state.check_alive()?;
+        state.writes += 1;
+        if state.fail_at_write == Some(state.writes) {
+            state.crashed = true;
+            return Err(Box::new(std::io::Error::other("Simulated crash")));
+        }
+        let change = Unsynced::Write { offset, data: data.to_vec() };
+        let mut file = self.data.borrow_mut();
+        apply(&mut file.current, &change);
+        file.unsynced.push(change);
+        Ok(())
+    }
+
+    fn size(&mut self) -> Result<u64, Box<dyn Error>> {
+        self.state.borrow().check_alive()?;
+        Ok(self.data.borrow().current.len() as u64)
+    }
+
+    fn set_size(&mut self, size: u64) -> Result<(), Box<dyn Error>> {
+        self.state.borrow().check_alive()?;
+        let change = Unsynced::SetSize(size);
+        let mut file = self.data.borrow_mut();
+        apply(&mut file.current, &change);
+        file.unsynced.push(change);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Box<dyn Error>> {
+        self.state.borrow().check_alive()?;
+        let mut file = self.data.borrow_mut();
+        for change in std::mem::take(&mut file.unsynced) {
+            apply(&mut file.durable, &change);
+        }
+        Ok(())
+    }
+}
+
+impl Vfs for MemVfs {
+    fn open(&self, path: &Path) -> Result<Box<dyn Storage>, Box<dyn Error>> {
+        let mut state = self.state.borrow_mut();
+        state.check_alive()?;
+        let data = state.files.entry(path.to_path_buf()).or_default().clone();
+        Ok(Box::new(MemFile { state: self.state.clone(), data }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.state.borrow().files.contains_key(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state.borrow_mut();
+        state.check_alive()?;
+        match state.files.remove(path) {
+            Some(_) => Ok(()),
+            None => Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound))),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state.borrow_mut();
+        state.check_alive()?;
+        match state.files.remove(from) {
+            Some(file) => {
+                state.files.insert(to.to_path_buf(), file);
+                Ok(())
+            },
+            None => Err(Box::new(std::io::Error::from(std::io::ErrorKind::NotFound))),
+        }
+    }
+}
@@ -1,176 +1,995 @@
-use std::{collections::HashMap, error::Error, fs::OpenOptions, io::{Read, Seek, Write}, path::Path};
-use crate::{btree::{get_node, new_leaf, Node}, cursor::{cursor_advance, cursor_insert, cursor_value, table_find, table_start}, utils::vec_to_page};
+use std::{collections::HashMap, error::Error, path::{Path, PathBuf}, rc::Rc};
+use std::ops::Bound;
+use crate::{btree::{bulk_load, cell_record, decode_node, new_leaf, Node}, cursor::{cursor_advance, cursor_delete, cursor_insert, cursor_retreat, cursor_row, cursor_update, cursor_value, table_find, table_range, table_range_rev, table_start}, utils::{read_u32, vec_to_page, write_u32, Page}};
+use crate::cache::PageCache;
+use crate::journal::{self, Journal};
+use crate::storage::{OsVfs, RamVfs, Storage, Vfs};
+use crate::wal::{self, Wal};
 use crate::constants;
+use crate::catalog::{add_entry, check_entry, read_catalog, EntryType};
+use crate::record::Value;
+use crate::schema::Schema;
+
+/// State of the pager when a savepoint was taken, plus the prior image of every page
+/// touched since then (`None` for pages that were not part of the tree).
+struct Savepoint {
+    name: String,
+    images: HashMap<u32, Option<Node>>,
+    num_pages: u32,
+    root_page_num: u32,
+    free_pages: Vec<u32>,
+    began_transaction: bool, // released last, it commits
+}
 
 pub struct Pager {
-    file: std::fs::File,
+    vfs: Rc<dyn Vfs>,
+    path: PathBuf,
+    file: Box<dyn Storage>,
     num_pages: u32,
-    pub pages: HashMap<u32, Node>
+    root_page_num: u32, // root of the system catalog, 0 until it is allocated
+    change_counter: u32,
+    free_pages: Vec<u32>, // every page of the free-list, trunks included
+    modified: bool, // pages, free-list or header changed since the last flush
+    journal: Option<Journal>, // open while the database file holds pages of an unfinished flush
+    journal_mode: JournalMode,
+    wal: Option<Wal>, // open in write-ahead log mode
+    in_transaction: bool, // changes are only flushed by commit
+    savepoints: Vec<Savepoint>, // innermost last
+    pub pages: PageCache,
 }
 
 impl Pager {
-    pub fn open(filename: &Path) -> Pager {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(filename)
-            .unwrap();
-
-        let file_length = file.metadata().unwrap().len() as u32;
-        if file_length % constants::PAGE_SIZE != 0 {
-            eprintln!("Db file is not a whole number of pages. Corrupt file.");
-            std::process::exit(1);
-        }
-
-        let num_pages = file_length / constants::PAGE_SIZE;
-        Pager {
+    /// Opens the database file at `filename` in `vfs`, rolling back an interrupted flush.
+    pub fn open(vfs: Rc<dyn Vfs>, filename: &Path) -> Result<Pager, Box<dyn Error>> {
+        let mut file = vfs.open(filename)?;
+        journal::recover(vfs.as_ref(), filename, file.as_mut())?;
+
+        // committed changes may only be in the log so far
+        let wal = match vfs.exists(&wal::wal_path(filename)) {
+            true => Some(Wal::open(vfs.as_ref(), filename)?),
+            false => None,
+        };
+        let mut pager = Pager {
+            vfs,
+            path: filename.to_path_buf(),
             file,
-            // file_length,
-            num_pages,
-            pages: HashMap::new(), 
+            num_pages: 0,
+            root_page_num: 0,
+            change_counter: 0,
+            free_pages: Vec::new(),
+            modified: false,
+            journal: None,
+            journal_mode: JournalMode::Rollback,
+            wal,
+            in_transaction: false,
+            savepoints: Vec::new(),
+            pages: PageCache::new(constants::DEFAULT_CACHE_CAPACITY),
+        };
+        pager.load_committed_state()?;
+
+        match (pager.journal_mode, pager.wal.take()) {
+            (JournalMode::Wal, None) => pager.wal = Some(Wal::open(pager.vfs.as_ref(), filename)?),
+            (JournalMode::Wal, Some(wal)) => pager.wal = Some(wal),
+            (JournalMode::Rollback, Some(mut wal)) => {
+                // left behind by a switch back to the rollback journal
+                wal.checkpoint(pager.file.as_mut())?;
+                wal.delete(pager.vfs.as_ref())?;
+            },
+            (JournalMode::Rollback, None) => (),
+        }
+        Ok(pager)
+    }
+
+    /// Reads the size, header and free-list of the database as of the last commit.
+    fn load_committed_state(&mut self) -> Result<(), Box<dyn Error>> {
+        match self.wal.as_ref().map_or(0, |wal| wal.db_pages()) {
+            // a checkpoint cut short may have left a torn page at the end of the file,
+            // the log still has all of it
+            0 => {
+                let file_length = self.file.size()?;
+                if !file_length.is_multiple_of(constants::PAGE_SIZE as u64) {
+                    return Err(Box::new(std::io::Error::other("Db file is not a whole number of pages. Corrupt file.")));
+                }
+                self.num_pages = (file_length / constants::PAGE_SIZE as u64) as u32;
+            },
+            db_pages => self.num_pages = db_pages,
+        }
+
+        self.free_pages.clear();
+        if self.num_pages == 0 {
+            // New db file: only the header page so far
+            self.num_pages = 1;
+            self.root_page_num = 0;
+            return Ok(());
+        }
+        self.read_header()
+    }
+
+    /// Validates the file header and loads the fields it holds, free-list included.
+    fn read_header(&mut self) -> Result<(), Box<dyn Error>> {
+        let header = self.read_raw_page(constants::FILE_HEADER_PAGE_NUM)?;
+        let magic_start = constants::HEADER_MAGIC_OFFSET as usize;
+        if &header[magic_start..magic_start + constants::HEADER_MAGIC.len()] != constants::HEADER_MAGIC {
+            return Err(Box::new(std::io::Error::other("File is not a SQLighter database")));
+        }
+
+        let version = read_u32(&header, constants::HEADER_VERSION_OFFSET);
+        if version > constants::FORMAT_VERSION {
+            return Err(Box::new(std::io::Error::other(format!(
+                "Database file format version {} is newer than the supported version {}", version, constants::FORMAT_VERSION))));
+        }
+        if version < constants::MIN_FORMAT_VERSION {
+            return Err(Box::new(std::io::Error::other(format!(
+                "Database file format version {} is older than the oldest supported version {}", version, constants::MIN_FORMAT_VERSION))));
+        }
+
+        let page_size = read_u32(&header, constants::HEADER_PAGE_SIZE_OFFSET);
+        if page_size != constants::PAGE_SIZE {
+            return Err(Box::new(std::io::Error::other(format!(
+                "Database page size {} does not match the supported page size {}", page_size, constants::PAGE_SIZE))));
+        }
+
+        let root_page_num = read_u32(&header, constants::HEADER_ROOT_PAGE_OFFSET);
+        if root_page_num == constants::FILE_HEADER_PAGE_NUM || root_page_num >= self.num_pages {
+            return Err(Box::new(std::io::Error::other("Root page number out of bounds. Corrupt file.")));
+        }
+        if root_page_num != constants::CATALOG_ROOT_PAGE_NUM {
+            return Err(Box::new(std::io::Error::other(format!(
+                "The system catalog must start on page {}. Corrupt file.", constants::CATALOG_ROOT_PAGE_NUM))));
+        }
+        self.root_page_num = root_page_num;
+        self.change_counter = read_u32(&header, constants::HEADER_CHANGE_COUNTER_OFFSET);
+        self.journal_mode = match read_u32(&header, constants::HEADER_JOURNAL_MODE_OFFSET) {
+            0 => JournalMode::Rollback,
+            1 => JournalMode::Wal,
+            mode => return Err(Box::new(std::io::Error::other(format!("Unknown journal mode {}. Corrupt file.", mode)))),
+        };
+
+        let trunk = read_u32(&header, constants::HEADER_FREELIST_TRUNK_OFFSET);
+        let count = read_u32(&header, constants::HEADER_FREELIST_COUNT_OFFSET);
+        self.load_free_list(trunk, count)
+    }
+
+    fn header_page(&self, freelist_trunk: u32) -> Page {
+        let mut header = [0; constants::PAGE_SIZE as usize];
+        let magic_start = constants::HEADER_MAGIC_OFFSET as usize;
+        header[magic_start..magic_start + constants::HEADER_MAGIC.len()].copy_from_slice(constants::HEADER_MAGIC);
+        write_u32(&mut header, constants::HEADER_VERSION_OFFSET, constants::FORMAT_VERSION);
+        write_u32(&mut header, constants::HEADER_PAGE_SIZE_OFFSET, constants::PAGE_SIZE);
+        write_u32(&mut header, constants::HEADER_ROOT_PAGE_OFFSET, self.root_page_num);
+        write_u32(&mut header, constants::HEADER_FREELIST_TRUNK_OFFSET, freelist_trunk);
+        write_u32(&mut header, constants::HEADER_FREELIST_COUNT_OFFSET, self.free_pages.len() as u32);
+        write_u32(&mut header, constants::HEADER_CHANGE_COUNTER_OFFSET, self.change_counter);
+        write_u32(&mut header, constants::HEADER_JOURNAL_MODE_OFFSET, match self.journal_mode {
+            JournalMode::Rollback => 0,
+            JournalMode::Wal => 1,
+        });
+        header
+    }
+
+    /// Root of the system catalog, `constants::CATALOG_ROOT_PAGE_NUM` once allocated.
+    pub fn root_page_num(&self) -> u32 {
+        self.root_page_num
+    }
+
+    pub fn set_root_page_num(&mut self, page_num: u32) {
+        self.root_page_num = page_num;
+        self.modified = true;
+    }
+
+
+    /// Number of times the file has been written to, bumped on every flush.
+    pub fn change_counter(&self) -> u32 {
+        self.change_counter
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    /// Starts an explicit transaction: nothing is flushed until `commit`, and `rollback`
+    /// brings the database back to its state at this point.
+    pub fn begin(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.in_transaction {
+            return Err(Box::new(std::io::Error::other("Cannot start a transaction within a transaction")));
         }
+        self.flush()?;
+        self.in_transaction = true;
+        Ok(())
     }
 
-    pub fn insert_page(&mut self, page: Node) -> Result<(), Box<dyn Error>> {
-        let page_num = self.num_pages;
-        self.pages.insert(page_num, page);
+    pub fn commit(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.in_transaction {
+            return Err(Box::new(std::io::Error::other("Cannot commit: no transaction is active")));
+        }
+        self.flush()?;
+        self.in_transaction = false;
+        self.savepoints.clear();
         Ok(())
     }
 
+    pub fn rollback(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.in_transaction {
+            return Err(Box::new(std::io::Error::other("Cannot rollback: no transaction is active")));
+        }
+        self.in_transaction = false;
+        self.savepoints.clear();
+        self.discard_changes()
+    }
 
-    pub fn get_page(&mut self, page_num: u32) -> Result<&mut Node, Box<dyn Error>> {
-        if page_num >= constants::TABLE_MAX_PAGES {
-            return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Tried to fetch page number out of bounds")))
+    /// Marks a point `rollback_to` can return to without undoing the whole transaction.
+    /// Outside of a transaction, starts one that releasing this savepoint commits.
+    pub fn savepoint(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let began_transaction = !self.in_transaction;
+        if began_transaction {
+            self.begin()?;
         }
+        self.savepoints.push(Savepoint {
+            name: name.to_string(),
+            images: HashMap::new(),
+            num_pages: self.num_pages,
+            root_page_num: self.root_page_num,
+            free_pages: self.free_pages.clone(),
+            began_transaction,
+        });
+        Ok(())
+    }
 
-        if self.pages.contains_key(&page_num) {
-            return Ok(self.pages.get_mut(&page_num).unwrap());
+    fn find_savepoint(&self, name: &str) -> Result<usize, Box<dyn Error>> {
+        match self.savepoints.iter().rposition(|savepoint| savepoint.name == name) {
+            Some(idx) => Ok(idx),
+            None => Err(Box::new(std::io::Error::other(format!("No such savepoint: {}", name)))),
         }
+    }
 
-        match self.load_page_from_disk(page_num) {
-            Ok(_) => (),
-            Err(_e) => {
-                return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Error loading page from disk. Probably fetching not existing page number.")));
+    /// Forgets the savepoint `name` and every later one, keeping their changes.
+    pub fn release(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let idx = self.find_savepoint(name)?;
+        let released = self.savepoints.split_off(idx);
+        if idx == 0 {
+            if released[0].began_transaction {
+                return self.commit();
             }
+            return Ok(());
         }
+        // the enclosing savepoint takes over the oldest image of each page
+        let parent = &mut self.savepoints[idx - 1];
+        for savepoint in released {
+            for (page_num, image) in savepoint.images {
+                parent.images.entry(page_num).or_insert(image);
+            }
+        }
+        Ok(())
+    }
 
-        Ok(self.pages.get_mut(&page_num).unwrap())
+    /// Undoes every change made since the savepoint `name` was taken. The savepoint stays.
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let idx = self.find_savepoint(name)?;
+        let mut undone = self.savepoints.split_off(idx);
+        // newest images first, so the oldest image of a page is the one left in the cache
+        for savepoint in undone.iter_mut().rev() {
+            for (page_num, image) in savepoint.images.drain() {
+                match image {
+                    Some(node) => {
+                        self.evict_down_to(self.pages.capacity() - 1)?;
+                        self.pages.insert(page_num, node, true);
+                    },
+                    None => {
+                        self.pages.remove(page_num);
+                    },
+                }
+            }
+        }
+        let savepoint = undone.swap_remove(0);
+        self.num_pages = savepoint.num_pages;
+        self.root_page_num = savepoint.root_page_num;
+        self.free_pages = savepoint.free_pages.clone();
+        self.modified = true;
+        self.savepoints.push(savepoint);
+        Ok(())
     }
 
-    fn load_page_from_disk(&mut self, page_num: u32) -> Result<(), Box<dyn Error>> {
+    /// Keeps what `page_num` holds now in the innermost savepoint, the first time it changes.
+    fn save_prior_image(&mut self, page_num: u32, image: Option<Node>) {
+        if let Some(savepoint) = self.savepoints.last_mut() {
+            savepoint.images.entry(page_num).or_insert(image);
+        }
+    }
+
+    fn has_prior_image(&self, page_num: u32) -> bool {
+        self.savepoints.last().is_none_or(|savepoint| savepoint.images.contains_key(&page_num))
+    }
+
+    /// Throws away every change since the last flush, including pages already written out
+    /// by evictions: the journal puts them back, or the log forgets its uncommitted frames.
+    fn discard_changes(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.journal.take().is_some() {
+            journal::recover(self.vfs.as_ref(), &self.path, self.file.as_mut())?;
+        }
+        if let Some(wal) = self.wal.as_mut() {
+            wal.rollback();
+        }
+        // clean pages too, they may have been read back after an eviction
+        self.pages.clear();
+        self.modified = false;
+        self.load_committed_state()
+    }
+
+    fn check_no_transaction(&self, operation: &str) -> Result<(), Box<dyn Error>> {
+        if self.in_transaction {
+            return Err(Box::new(std::io::Error::other(format!("Cannot {} within a transaction", operation))));
+        }
+        Ok(())
+    }
+
+    pub fn journal_mode(&self) -> JournalMode {
+        self.journal_mode
+    }
+
+    /// Switches between the rollback journal and the write-ahead log. Pending changes are
+    /// committed and, when leaving WAL mode, copied back from the log first.
+    pub fn set_journal_mode(&mut self, mode: JournalMode) -> Result<(), Box<dyn Error>> {
+        self.check_no_transaction("change the journal mode")?;
+        if mode == self.journal_mode {
+            return Ok(());
+        }
+        self.wal_checkpoint()?;
+        if let Some(wal) = self.wal.take() {
+            wal.delete(self.vfs.as_ref())?;
+        }
+        // the new mode is recorded in the header through the rollback journal
+        self.journal_mode = mode;
+        self.modified = true;
+        self.flush()?;
+        if mode == JournalMode::Wal {
+            self.wal = Some(Wal::open(self.vfs.as_ref(), &self.path)?);
+        }
+        Ok(())
+    }
+
+    /// Commits pending changes and, in WAL mode, copies the log back to the database file.
+    pub fn wal_checkpoint(&mut self) -> Result<(), Box<dyn Error>> {
+        self.check_no_transaction("checkpoint the log")?;
+        self.flush()?;
+        if let Some(wal) = self.wal.as_mut() {
+            wal.checkpoint(self.file.as_mut())?;
+        }
+        Ok(())
+    }
+
+    /// Committed frames waiting in the write-ahead log for a checkpoint.
+    pub fn wal_frame_count(&self) -> u32 {
+        self.wal.as_ref().map_or(0, |wal| wal.frame_count())
+    }
+
+    pub fn free_page_count(&self) -> u32 {
+        self.free_pages.len() as u32
+    }
+
+    fn read_raw_page(&mut self, page_num: u32) -> Result<Page, Box<dyn Error>> {
+        if let Some(wal) = self.wal.as_mut() {
+            if let Some(page) = wal.read_page(page_num)? {
+                return Ok(page);
+            }
+        }
         let mut page_data = vec![0; constants::PAGE_SIZE as usize];
-        let offset = page_num * constants::PAGE_SIZE;
-        self.file.seek(std::io::SeekFrom::Start(offset as u64))?;
-        self.file.read_exact(&mut page_data)?;
-        let page = vec_to_page(page_data.as_mut());
-        
-        self.pages.entry(page_num).or_insert(get_node(page)?);
-    
+        self.file.read_at(page_num as u64 * constants::PAGE_SIZE as u64, &mut page_data)?;
+        Ok(vec_to_page(page_data.as_mut()))
+    }
+
+    /// Decodes the node stored on `page_num`. A memory-mapped file is decoded in place,
+    /// without reading the page into a buffer first.
+    fn decode_raw_page(&mut self, page_num: u32) -> Result<Node, Box<dyn Error>> {
+        if let Some(wal) = self.wal.as_mut() {
+            if let Some(page) = wal.read_page(page_num)? {
+                return decode_node(&page);
+            }
+        }
+        if let Some(map) = self.file.mapped()? {
+            let start = page_num as usize * constants::PAGE_SIZE as usize;
+            return match map.get(start..start + constants::PAGE_SIZE as usize) {
+                Some(page) => decode_node(page.try_into()?),
+                None => Err(Box::new(std::io::Error::from(std::io::ErrorKind::UnexpectedEof))),
+            };
+        }
+        decode_node(&self.read_raw_page(page_num)?)
+    }
+
+    fn write_raw_page(&mut self, page_num: u32, page: &Page) -> Result<(), Box<dyn Error>> {
+        self.file.write_at(page_num as u64 * constants::PAGE_SIZE as u64, page)
+    }
+
+    /// Walks the chain of free-list trunk pages starting at `trunk`.
+    fn load_free_list(&mut self, mut trunk: u32, count: u32) -> Result<(), Box<dyn Error>> {
+        while trunk != 0 {
+            if trunk >= self.num_pages || self.free_pages.len() as u32 >= count {
+                return Err(Box::new(std::io::Error::other("Free-list trunk out of bounds")));
+            }
+            let page = self.read_raw_page(trunk)?;
+            self.free_pages.push(trunk);
+            let num_leaves = read_u32(&page, constants::FREELIST_TRUNK_COUNT_OFFSET).min(constants::FREELIST_TRUNK_MAX_LEAVES);
+            for i in 0..num_leaves {
+                self.free_pages.push(read_u32(&page, constants::FREELIST_TRUNK_LEAVES_OFFSET + i * 4));
+            }
+            trunk = read_u32(&page, constants::FREELIST_TRUNK_NEXT_OFFSET);
+        }
+
+        if self.free_pages.len() as u32 != count {
+            return Err(Box::new(std::io::Error::other("Free-list page count does not match the header")));
+        }
+        Ok(())
+    }
+
+    /// Lays out the free-list as a chain of trunk pages, each listing up to
+    /// FREELIST_TRUNK_MAX_LEAVES other free pages. Returns the first trunk (0 if the list
+    /// is empty) and the trunk pages to write.
+    fn free_list_pages(&self) -> (u32, Vec<(u32, Page)>) {
+        let chunks = self.free_pages.chunks(constants::FREELIST_TRUNK_MAX_LEAVES as usize + 1).collect::<Vec<&[u32]>>();
+        let mut pages = Vec::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let next = chunks.get(i + 1).map_or(0, |next| next[0]);
+            let mut page = [0; constants::PAGE_SIZE as usize];
+            write_u32(&mut page, constants::FREELIST_TRUNK_NEXT_OFFSET, next);
+            write_u32(&mut page, constants::FREELIST_TRUNK_COUNT_OFFSET, chunk.len() as u32 - 1);
+            for (j, leaf) in chunk[1..].iter().enumerate() {
+                write_u32(&mut page, constants::FREELIST_TRUNK_LEAVES_OFFSET + j as u32 * 4, *leaf);
+            }
+            pages.push((chunk[0], page));
+        }
+        (chunks.first().map_or(0, |c| c[0]), pages)
+    }
+
+    /// Saves the current content of `page_nums` to the rollback journal, starting one if
+    /// needed, so they can be overwritten in the database file.
+    fn journal_pages(&mut self, page_nums: &[u32]) -> Result<(), Box<dyn Error>> {
+        let mut journal = match self.journal.take() {
+            Some(journal) => journal,
+            None => {
+                let original_pages = (self.file.size()? / constants::PAGE_SIZE as u64) as u32;
+                Journal::create(self.vfs.as_ref(), &self.path, original_pages)?
+            },
+        };
+        for page_num in page_nums {
+            if journal.needs(*page_num) {
+                let page = self.read_raw_page(*page_num)?;
+                journal.append(*page_num, &page)?;
+            }
+        }
+        journal.sync()?;
+        self.journal = Some(journal);
+        Ok(())
+    }
+
+    /// Stores `page` on a fresh page number and returns it.
+    pub fn insert_page(&mut self, page: Node) -> Result<u32, Box<dyn Error>> {
+        let page_num = match self.free_pages.pop() {
+            Some(page_num) => page_num,
+            None => {
+                self.num_pages += 1;
+                self.num_pages - 1
+            },
+        };
+        self.save_prior_image(page_num, None);
+        self.evict_down_to(self.pages.capacity() - 1)?;
+        self.pages.insert(page_num, page, true);
+        self.modified = true;
+        Ok(page_num)
+    }
+
+
+    /// Drops a page that is no longer part of the tree so its number can be handed out again.
+    pub fn free_page(&mut self, page_num: u32) -> Result<(), Box<dyn Error>> {
+        if !self.has_prior_image(page_num) {
+            let node = self.get_page(page_num)?.clone();
+            self.save_prior_image(page_num, Some(node));
+        }
+        self.pages.remove(page_num);
+        self.free_pages.push(page_num);
+        self.modified = true;
+        Ok(())
+    }
+
+    /// Keeps `page_num` in the cache until it is unpinned as many times.
+    pub fn pin(&mut self, page_num: u32) {
+        self.pages.pin(page_num);
+    }
+
+    pub fn unpin(&mut self, page_num: u32) {
+        self.pages.unpin(page_num);
+    }
+
+    /// Sets how many pages the cache holds before evicting, writing back dirty pages if it shrinks.
+    pub fn set_cache_capacity(&mut self, capacity: usize) -> Result<(), Box<dyn Error>> {
+        self.pages.set_capacity(capacity);
+        self.evict_down_to(self.pages.capacity())
+    }
+
+    /// Evicts pages until at most `keep` are cached (or all the others are pinned),
+    /// writing back the dirty ones.
+    fn evict_down_to(&mut self, keep: usize) -> Result<(), Box<dyn Error>> {
+        while let Some((page_num, page)) = self.pages.evict(keep) {
+            if page.dirty {
+                let data = page.node.to_page();
+                match self.wal.as_mut() {
+                    Some(wal) => wal.append(page_num, &data)?,
+                    None => {
+                        self.journal_pages(&[page_num])?;
+                        self.write_raw_page(page_num, &data)?;
+                    },
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_page_num(&self, page_num: u32) -> Result<(), Box<dyn Error>> {
+        if page_num == constants::FILE_HEADER_PAGE_NUM {
+            return Err(Box::new(std::io::Error::other("Page 0 holds the file header, not a node")))
+        }
+        if page_num >= self.num_pages {
+            return Err(Box::new(std::io::Error::other("Tried to fetch page number out of bounds")))
+        }
+        Ok(())
+    }
+
+    pub fn get_page(&mut self, page_num: u32) -> Result<&Node, Box<dyn Error>> {
+        self.check_page_num(page_num)?;
+        if !self.pages.contains(page_num) {
+            self.load_page_from_disk(page_num)?;
+        }
+        Ok(self.pages.get(page_num).unwrap())
+    }
+
+    /// Same as `get_page`, for a page about to be modified: it will be written back on flush or eviction.
+    pub fn get_page_mut(&mut self, page_num: u32) -> Result<&mut Node, Box<dyn Error>> {
+        self.check_page_num(page_num)?;
+        if !self.pages.contains(page_num) {
+            self.load_page_from_disk(page_num)?;
+        }
+        if !self.has_prior_image(page_num) {
+            let node = self.pages.get(page_num).unwrap().clone();
+            self.save_prior_image(page_num, Some(node));
+        }
+        self.modified = true;
+        Ok(self.pages.get_mut(page_num).unwrap())
+    }
+
+    fn load_page_from_disk(&mut self, page_num: u32) -> Result<(), Box<dyn Error>> {
+        let node = match self.decode_raw_page(page_num) {
+            Ok(node) => node,
+            Err(_e) => {
+                return Err(Box::new(std::io::Error::other("Error loading page from disk. Probably fetching not existing page number.")));
+            }
+        };
+        self.evict_down_to(self.pages.capacity() - 1)?;
+        self.pages.insert(page_num, node, false);
         Ok(())
     }
     
+    /// Writes the pages modified since the last flush, then the free-list and the header,
+    /// and syncs the file. Does nothing if there is no change to persist. The original
+    /// pages are journaled first, so a crash midway is rolled back on the next open.
+    /// In WAL mode the pages are appended to the log as one commit instead.
     pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.modified {
+            return Ok(());
+        }
+
+        let mut pages = self.pages.iter()
+            .filter(|(_, page)| page.dirty)
+            .map(|(page_num, page)| (page_num, page.node.clone().to_page()))
+            .collect::<Vec<(u32, Page)>>();
+        let (freelist_trunk, trunks) = self.free_list_pages();
+        pages.extend(trunks);
+        self.change_counter = self.change_counter.wrapping_add(1);
+        pages.push((constants::FILE_HEADER_PAGE_NUM, self.header_page(freelist_trunk)));
+
+        if let Some(wal) = self.wal.as_mut() {
+            wal.commit(pages, self.num_pages)?;
+            self.pages.mark_clean();
+            self.modified = false;
+            if wal.frame_count() >= constants::WAL_AUTOCHECKPOINT_FRAMES {
+                wal.checkpoint(self.file.as_mut())?;
+            }
+            return Ok(());
+        }
+
+        self.journal_pages(&pages.iter().map(|(page_num, _)| *page_num).collect::<Vec<u32>>())?;
+        for (page_num, page) in pages {
+            self.write_raw_page(page_num, &page)?;
+        }
+        self.pages.mark_clean();
 
-       for (page_num, page_data) in &self.pages {
-            let offset = page_num * constants::PAGE_SIZE;
-            self.file.seek(std::io::SeekFrom::Start(offset as u64))?;
-            self.file.write_all(&mut page_data.clone().to_page())?;
+        // free pages that were never written still have to exist in the file
+        let file_length = self.num_pages as u64 * constants::PAGE_SIZE as u64;
+        if self.file.size()? < file_length {
+            self.file.set_size(file_length)?;
         }
 
-        self.file.sync_all()?;
+        self.file.sync()?;
+        if let Some(journal) = self.journal.take() {
+            journal.delete(self.vfs.as_ref())?;
+        }
+        self.modified = false;
         Ok(())
     } 
 }
 
 
-pub struct Table {
+/// A database file: the pager and every table the system catalog lists.
+pub struct Database {
     pub pager: Pager,
-    pub root_page_num: u32,
+    pub tables: Vec<Table>, // in the order they were created
 }
 
+/// A table of the database: where its B-tree starts and the columns of its rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub root_page_num: u32,
+    pub schema: Schema,
+}
 
-
-#[derive(Debug, Clone, Copy)]
+/// A row of a table: its key, then a value for each other column of the schema, in order.
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Row {
     pub id: u32,
-    pub username: [u8; 32], 
-    pub email: [u8; 255],
+    pub fields: Vec<Value>,
 }
 
 impl Row {
     pub fn new() -> Row {
         Row {
             id: 0,
-            username: [0; 32],
-            email: [0; 255],
+            fields: Vec::new(),
         }
     }
 }
 
-pub fn serialize_row(source: &Row, dest: &mut [u8]) {
-    dest[0..4].copy_from_slice(&source.id.to_le_bytes());
-    dest[4..36].copy_from_slice(&source.username);
-    dest[36..291].copy_from_slice(&source.email);
+/// New values for the columns set by an `update` statement.
+#[derive(Debug, Clone, Default)]
+pub struct RowUpdate {
+    pub id: Option<u32>,
+    pub fields: Vec<(usize, Value)>, // index in `Row::fields`, new value
 }
 
-pub fn deserialize_row(source: &[u8]) -> Result<Row, Box<dyn Error>> {
-    let id = u32::from_le_bytes(source[0..4].try_into()?);
-    let mut username = [0; 32];
-    username.copy_from_slice(&source[4..36]);
-    let mut email = [0; 255];
-    email.copy_from_slice(&source[36..291]);
-    Ok(Row { id, username, email })
+impl RowUpdate {
+    /// Sets the new values on `row`, read from its table so it has every field.
+    pub fn apply(&self, row: &mut Row) {
+        if let Some(id) = self.id {
+            row.id = id;
+        }
+        for (i, field) in &self.fields {
+            if let Some(old) = row.fields.get_mut(*i) {
+                *old = field.clone();
+            }
+        }
+    }
 }
 
 
-impl Table {
-    pub fn db_open(path: String) -> Table {
-        let mut pager = Pager::open(Path::new(&path));
-        match pager.get_page(0) {
-            Ok(_) => (),
-            Err(_) => {
-                // New db file
-                let root_node = new_leaf();
-                pager.pages.insert(0, root_node);
+impl Database {
+    /// Opens the database file at `path`, creating it if missing. `:memory:` opens a new
+    /// database that only lives in memory, until the database is dropped.
+    pub fn open(path: String) -> Result<Database, Box<dyn Error>> {
+        if path == constants::MEMORY_DB_PATH {
+            return Database::open_with_vfs(path, Rc::new(RamVfs::new()));
+        }
+        Database::open_with_vfs(path, Rc::new(OsVfs))
+    }
+
+    /// Same as `open`, for a database whose files live in `vfs`.
+    pub fn open_with_vfs(path: String, vfs: Rc<dyn Vfs>) -> Result<Database, Box<dyn Error>> {
+        let mut pager = Pager::open(vfs, Path::new(&path))?;
+        if pager.root_page_num() == 0 {
+            // New db file: the catalog, then the users table it lists
+            let catalog_page_num = pager.insert_page(new_leaf())?;
+            pager.set_root_page_num(catalog_page_num);
+            let root_page_num = pager.insert_page(new_leaf())?;
+            add_entry(&mut pager, EntryType::Table, constants::DEFAULT_TABLE_NAME, root_page_num, constants::DEFAULT_TABLE_SQL)?;
+            // a rollback must never go back to a file without a root
+            pager.flush()?;
+        }
+
+        let mut db = Database { pager, tables: Vec::new() };
+        db.load_tables()?;
+        Ok(db)
+    }
+
+    /// Reads the tables back from the system catalog.
+    fn load_tables(&mut self) -> Result<(), Box<dyn Error>> {
+        self.tables.clear();
+        for entry in read_catalog(&mut self.pager)? {
+            if entry.root_page_num == constants::FILE_HEADER_PAGE_NUM || entry.root_page_num >= self.pager.num_pages {
+                return Err(Box::new(std::io::Error::other(format!("Root page of {} out of bounds. Corrupt file.", entry.name))));
             }
+            self.tables.push(Table { root_page_num: entry.root_page_num, schema: Schema::from_sql(&entry.sql)? });
         }
-        
-        Table {
-            root_page_num: 0,
-            pager 
+        Ok(())
+    }
+
+    /// The table called `name`.
+    pub fn table(&self, name: &str) -> Result<&Table, Box<dyn Error>> {
+        match self.tables.iter().find(|table| table.schema.name == name) {
+            Some(table) => Ok(table),
+            None => Err(Box::new(std::io::Error::other(format!("No such table: {}", name)))),
         }
     }
+
+    /// Adds the table `schema` describes, on a new root page, and records it in the catalog.
+    /// Like any other write, it is only durable once flushed, and a rollback undoes it.
+    pub fn create_table(&mut self, schema: Schema) -> Result<(), Box<dyn Error>> {
+        if schema.name == constants::CATALOG_TABLE_NAME {
+            return Err(Box::new(std::io::Error::other(format!("Table name {} is reserved for the system catalog", schema.name))));
+        }
+        if self.tables.iter().any(|table| table.schema.name == schema.name) {
+            return Err(Box::new(std::io::Error::other(format!("Table {} already exists", schema.name))));
+        }
+        check_entry(&schema.name, &schema.sql)?;
+
+        let root_page_num = self.pager.insert_page(new_leaf())?;
+        add_entry(&mut self.pager, EntryType::Table, &schema.name, root_page_num, &schema.sql)?;
+        self.tables.push(Table { root_page_num, schema });
+        Ok(())
+    }
+
+    /// Rolls back the transaction, forgetting the tables it created.
+    pub fn rollback(&mut self) -> Result<(), Box<dyn Error>> {
+        self.pager.rollback()?;
+        self.load_tables()
+    }
+
+    /// Undoes every change since the savepoint `name`, tables created since included.
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        self.pager.rollback_to(name)?;
+        self.load_tables()
+    }
    
-    pub fn db_close(&mut self) {
+    /// Flushes pending changes. An unfinished transaction is rolled back.
+    pub fn close(&mut self) {
+        if self.pager.in_transaction() {
+            self.pager.rollback().unwrap();
+        }
         self.pager.flush().unwrap();
     }
 
+    /// Makes every change so far durable without closing the database, so a crash only loses
+    /// what happens after it. Cheap to call often: only modified pages are written.
+    /// Within a transaction this does nothing: its changes become durable on commit.
+    pub fn checkpoint(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.pager.in_transaction() {
+            return Ok(());
+        }
+        self.pager.flush()
+    }
+
+    /// Rebuilds every tree, fully packed and without free pages, into a new file that then
+    /// replaces the database file. Pending changes are flushed first. Returns the number
+    /// of bytes the file shrank by.
+    pub fn vacuum(&mut self) -> Result<u64, Box<dyn Error>> {
+        self.pager.check_no_transaction("vacuum")?;
+        // in WAL mode the log is emptied, so it holds nothing of the old file after the swap
+        self.pager.wal_checkpoint()?;
+        let path = self.pager.path.clone();
+        let vfs = self.pager.vfs.clone();
+        let old_length = self.pager.file.size()?;
+
+        let entries = read_catalog(&mut self.pager)?;
+        let mut vacuum_path = path.clone().into_os_string();
+        vacuum_path.push("-vacuum");
+        let vacuum_path = PathBuf::from(vacuum_path);
+        // a vacuum cut short leaves its file behind, and a journal or log next to it would be
+        // replayed into the new file on open
+        for leftover in [vacuum_path.clone(), journal::journal_path(&vacuum_path), wal::wal_path(&vacuum_path)] {
+            if let Err(e) = vfs.remove(&leftover) {
+                if e.downcast_ref::<std::io::Error>().map(|e| e.kind()) != Some(std::io::ErrorKind::NotFound) {
+                    return Err(e);
+                }
+            }
+        }
+        {
+            let mut pager = Pager::open(vfs.clone(), &vacuum_path)?;
+            let catalog_page_num = pager.insert_page(new_leaf())?;
+            pager.set_root_page_num(catalog_page_num);
+            pager.change_counter = self.pager.change_counter;
+            pager.journal_mode = self.pager.journal_mode;
+            // every tree is copied to new pages, one row at a time, and the catalog then
+            // lists where they start
+            for entry in &entries {
+                let root_page_num = pager.insert_page(new_leaf())?;
+                let mut cursor = table_start(&mut self.pager, entry.root_page_num)?;
+                let rows = std::iter::from_fn(|| {
+                    if cursor.end_of_table {
+                        return None;
+                    }
+                    // overflow pages are copied too: the new file gets its own chains
+                    let row = match cursor_value(&mut cursor) {
+                        Some(cell) => cell_record(cursor.pager, &cell).map(|record| (cell.key, record)),
+                        None => Err(Box::new(std::io::Error::other(format!("Failed to copy a row of {}", entry.name))) as Box<dyn Error>),
+                    };
+                    cursor_advance(&mut cursor);
+                    Some(row)
+                });
+                bulk_load(&mut pager, root_page_num, rows)?;
+                add_entry(&mut pager, entry.entry_type, &entry.name, root_page_num, &entry.sql)?;
+            }
+            pager.flush()?;
+        }
+
+        // rename is atomic: the file is either the old or the new database, never a mix
+        vfs.rename(&vacuum_path, &path)?;
+        self.pager = Pager::open(vfs, &path)?;
+        self.load_tables()?;
+        let new_length = self.pager.file.size()?;
+        Ok(old_length.saturating_sub(new_length))
+    }
+
 }
 
-pub fn insert_row(table: &mut Table, row: Row) -> Result<(), Box<dyn Error>> {
-    // TODO: Add btree insertion logic here
-    let mut cursor = table_find(table, row.id)?;
+/// The pager and the table called `name`, borrowed together to walk the table's tree.
+fn open_table<'a>(db: &'a mut Database, name: &str) -> Result<(&'a mut Pager, &'a Table), Box<dyn Error>> {
+    match db.tables.iter().find(|table| table.schema.name == name) {
+        Some(table) => Ok((&mut db.pager, table)),
+        None => Err(Box::new(std::io::Error::other(format!("No such table: {}", name)))),
+    }
+}
 
-    cursor_insert(&mut cursor, row) 
+pub fn insert_row(db: &mut Database, name: &str, mut row: Row) -> Result<(), Box<dyn Error>> {
+    let (pager, table) = open_table(db, name)?;
+    table.schema.conform(&mut row)?;
+    let mut cursor = table_find(pager, table.root_page_num, row.id)?;
+    if let Some(cell) = cursor_value(&mut cursor) {
+        if cell.key == row.id {
+            return Err(Box::new(std::io::Error::other("Key already exists")));
+        }
+    }
+
+    cursor_insert(&mut cursor, &row)
+}
+
+pub fn delete_row(db: &mut Database, name: &str, id: u32) -> Result<bool, Box<dyn Error>> {
+    Ok(delete_rows(db, name, (Bound::Included(id), Bound::Included(id)))? == 1)
 }
 
+/// Smallest id allowed by the start of `range`, `None` if the range is empty.
+fn range_start(range: &(Bound<u32>, Bound<u32>)) -> Option<u32> {
+    match range.0 {
+        Bound::Included(id) => Some(id),
+        Bound::Excluded(id) => id.checked_add(1),
+        Bound::Unbounded => Some(0),
+    }
+}
 
-pub fn select_all_rows(table: &mut Table) -> Result<Vec<Row>, Box<dyn Error>> {
+/// Deletes every row whose id falls in `range` and returns how many were removed.
+pub fn delete_rows(db: &mut Database, name: &str, range: (Bound<u32>, Bound<u32>)) -> Result<u32, Box<dyn Error>> {
+    let (pager, table) = open_table(db, name)?;
+    let mut from = match range_start(&range) {
+        Some(from) => from,
+        None => return Ok(0),
+    };
+
+    let mut deleted = 0;
+    loop {
+        // a range cursor settles on the next leaf when `from` is past the end of the one it
+        // lands on, so no row of the range is skipped
+        let mut cursor = table_range(pager, table.root_page_num, (Bound::Included(from), range.1))?;
+        if cursor.end_of_table {
+            break;
+        }
+        let key = match cursor_value(&mut cursor) {
+            Some(cell) => cell.key,
+            None => break,
+        };
+        cursor_delete(&mut cursor)?;
+        deleted += 1;
+
+        if key == u32::MAX {
+            break;
+        }
+        from = key + 1;
+    }
+
+    Ok(deleted)
+}
+
+/// Applies `update` to every row whose id falls in `range` and returns how many were changed.
+/// Rows whose id changes are moved to their new position in the tree.
+pub fn update_rows(db: &mut Database, name: &str, range: (Bound<u32>, Bound<u32>), update: &RowUpdate) -> Result<u32, Box<dyn Error>> {
+    let (pager, table) = open_table(db, name)?;
+    let mut ids = Vec::new();
+    let mut cursor = table_range(pager, table.root_page_num, range)?;
+    while !cursor.end_of_table {
+        if let Some(cell) = cursor_value(&mut cursor) {
+            ids.push(cell.key);
+        }
+        cursor_advance(&mut cursor);
+    }
+    drop(cursor);
+
+    if update.id.is_some() && ids.len() > 1 {
+        return Err(Box::new(std::io::Error::other("Cannot set the same id on multiple rows")));
+    }
+
+    for id in &ids {
+        let (pager, table) = open_table(db, name)?;
+        let mut cursor = table_find(pager, table.root_page_num, *id)?;
+        let mut row = match cursor_row(&mut cursor)? {
+            Some(row) => row,
+            None => return Err(Box::new(std::io::Error::other("Failed to UPDATE row"))),
+        };
+        update.apply(&mut row);
+        table.schema.conform(&mut row)?;
+
+        if row.id == *id {
+            cursor_update(&mut cursor, &row)?;
+            continue;
+        }
+        drop(cursor);
+        if find_row(db, name, row.id)?.is_some() {
+            return Err(Box::new(std::io::Error::other("Key already exists")));
+        }
+        delete_row(db, name, *id)?;
+        insert_row(db, name, row)?;
+    }
+
+    Ok(ids.len() as u32)
+}
+
+pub fn find_row(db: &mut Database, name: &str, id: u32) -> Result<Option<Row>, Box<dyn Error>> {
+    let (pager, table) = open_table(db, name)?;
+    let mut cursor = table_find(pager, table.root_page_num, id)?;
+    match cursor_row(&mut cursor)? {
+        Some(row) if row.id == id => Ok(Some(row)),
+        _ => Ok(None),
+    }
+}
+
+
+pub fn select_all_rows(db: &mut Database, name: &str) -> Result<Vec<Row>, Box<dyn Error>> {
+    let (pager, table) = open_table(db, name)?;
     let mut res: Vec<Row> = Vec::new();
-    let mut cursor = table_start(table);
-    //TODO: REFACTOR THIS -> bpage structure should be used to traverse the btree
+    let mut cursor = table_start(pager, table.root_page_num)?;
     while !cursor.end_of_table {
-        match cursor_value(&mut cursor) {
-            Some(slot) => {
-                
-                let row = deserialize_row(&slot.value[..constants::ROW_SIZE as usize])?;
-                res.push(row);
-            },
-            None => return Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Failed to SELECT row"))),
+        match cursor_row(&mut cursor)? {
+            Some(row) => res.push(row),
+            None => return Err(Box::new(std::io::Error::other("Failed to SELECT row"))),
         }
         cursor_advance(&mut cursor);
     }
     
+    Ok(res)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JournalMode {
+    Rollback,
+    Wal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Ascending,
+    Descending,
+}
+
+/// Up to `limit` rows whose id falls in `range`, in `order`. Only the leaves covering the
+/// returned rows are visited.
+pub fn select_rows(db: &mut Database, name: &str, range: (Bound<u32>, Bound<u32>), order: Order, limit: Option<u32>) -> Result<Vec<Row>, Box<dyn Error>> {
+    let (pager, table) = open_table(db, name)?;
+    let mut res: Vec<Row> = Vec::new();
+    let mut cursor = match order {
+        Order::Ascending => table_range(pager, table.root_page_num, range)?,
+        Order::Descending => table_range_rev(pager, table.root_page_num, range)?,
+    };
+    while !cursor.end_of_table && limit.is_none_or(|limit| res.len() < limit as usize) {
+        match cursor_row(&mut cursor)? {
+            Some(row) => res.push(row),
+            None => return Err(Box::new(std::io::Error::other("Failed to SELECT row"))),
+        }
+        match order {
+            Order::Ascending => cursor_advance(&mut cursor),
+            Order::Descending => cursor_retreat(&mut cursor),
+        }
+    }
+
     Ok(res)
 }
\ No newline at end of file
@@ -6,27 +6,43 @@ pub type Page = [u8; constants::PAGE_SIZE as usize];
 
 pub fn vec_to_page(v: &mut [u8]) -> Page {
     let mut page = [0; constants::PAGE_SIZE as usize];
-    page[..v.len()].copy_from_slice(&v);
+    page[..v.len()].copy_from_slice(v);
     page
 }
 
-pub fn binary_search_key(cells: Vec<Cell>, key: u32) -> u32 {
-    if cells.is_empty() {
-        return 0;
-    }
-    
+// Every integer stored on disk is little-endian, whatever the host architecture.
+
+pub fn read_u32(page: &Page, offset: u32) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&page[offset as usize..(offset + 4) as usize]);
+    u32::from_le_bytes(bytes)
+}
+
+pub fn write_u32(page: &mut Page, offset: u32, value: u32) {
+    page[offset as usize..(offset + 4) as usize].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn read_u16(page: &Page, offset: u32) -> u16 {
+    u16::from_le_bytes([page[offset as usize], page[offset as usize + 1]])
+}
+
+pub fn write_u16(page: &mut Page, offset: u32, value: u16) {
+    page[offset as usize..(offset + 2) as usize].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn binary_search_key(cells: &[Cell], key: u32) -> u32 {
     let mut left: usize = 0;
-    let mut right: usize = cells.len() - 1; 
-    while left <= right {
+    let mut right: usize = cells.len();
+    while left < right {
         let mid = (left + right) / 2;
-        let mid_key = cells[mid as usize].key;
+        let mid_key = cells[mid].key;
         if mid_key == key {
             return mid as u32;
         }
         if mid_key < key {
             left = mid + 1;
         } else {
-            right = mid - 1;
+            right = mid;
         }
     }
 
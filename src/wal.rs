@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use crate::constants;
+use crate::storage::{Storage, Vfs};
+use crate::utils::{vec_to_page, Page};
+
+/// Write-ahead log. Commits append the modified pages as frames instead of overwriting the
+/// database file; the last frame of a commit records the database size and makes the whole
+/// commit visible. Frames are chained by a running checksum, so a commit cut short by a
+/// crash is ignored when the log is replayed. A checkpoint copies the latest frame of every
+/// page back to the database file and empties the log.
+pub struct Wal {
+    path: PathBuf,
+    file: Box<dyn Storage>,
+    salt: u32, // changes every time the log is emptied, so leftover frames never match
+    checksum: u32, // running checksum up to the last frame written
+    end: u64, // where the next frame goes
+    index: HashMap<u32, u64>, // page number -> offset of its latest committed frame
+    pending: HashMap<u32, u64>, // frames written since the last commit
+    committed_checksum: u32,
+    committed_end: u64,
+    db_pages: u32, // size of the database as of the last commit, 0 if none
+    frames: u32, // committed frames in the log
+}
+
+pub fn wal_path(db_path: &Path) -> PathBuf {
+    let mut path = db_path.to_path_buf().into_os_string();
+    path.push("-wal");
+    PathBuf::from(path)
+}
+
+fn checksum(seed: u32, frame_header: &[u8], page: &[u8]) -> u32 {
+    frame_header.chunks(4).chain(page.chunks(4)).fold(seed, |sum, word| {
+        sum.rotate_left(5) ^ u32::from_le_bytes(word.try_into().unwrap())
+    })
+}
+
+fn new_salt(previous: u32) -> u32 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos());
+    previous.wrapping_add(1) ^ nanos
+}
+
+impl Wal {
+    /// Opens the log next to `db_path`, creating it if missing, and replays its committed frames.
+    pub fn open(vfs: &dyn Vfs, db_path: &Path) -> Result<Wal, Box<dyn Error>> {
+        let path = wal_path(db_path);
+        let file = vfs.open(&path)?;
+        let mut wal = Wal {
+            path,
+            file,
+            salt: 0,
+            checksum: 0,
+            end: constants::WAL_HEADER_SIZE as u64,
+            index: HashMap::new(),
+            pending: HashMap::new(),
+            committed_checksum: 0,
+            committed_end: constants::WAL_HEADER_SIZE as u64,
+            db_pages: 0,
+            frames: 0,
+        };
+
+        let mut header = [0u8; constants::WAL_HEADER_SIZE as usize];
+        let valid_header = wal.file.read_at(0, &mut header).is_ok()
+            && &header[..constants::WAL_MAGIC.len()] == constants::WAL_MAGIC
+            && u32::from_le_bytes(header[8..12].try_into()?) == constants::PAGE_SIZE;
+        if valid_header {
+            wal.salt = u32::from_le_bytes(header[12..16].try_into()?);
+            wal.replay()?;
+        } else {
+            wal.reset()?;
+        }
+        Ok(wal)
+    }
+
+    fn replay(&mut self) -> Result<(), Box<dyn Error>> {
+        let size = self.file.size()?;
+        let mut checksum = self.salt;
+        self.committed_checksum = self.salt; // a log without commits chains its first frame to the salt
+        let mut offset = constants::WAL_HEADER_SIZE as u64;
+        let mut pending = HashMap::new();
+        let mut frame = vec![0u8; constants::WAL_FRAME_SIZE as usize];
+        while offset + constants::WAL_FRAME_SIZE as u64 <= size {
+            self.file.read_at(offset, &mut frame)?;
+            let (frame_header, page) = frame.split_at(constants::WAL_FRAME_HEADER_SIZE as usize);
+            let page_num = u32::from_le_bytes(frame_header[0..4].try_into()?);
+            let db_pages = u32::from_le_bytes(frame_header[4..8].try_into()?);
+            let salt = u32::from_le_bytes(frame_header[8..12].try_into()?);
+            let stored = u32::from_le_bytes(frame_header[12..16].try_into()?);
+            checksum = checksum_frame(checksum, page_num, db_pages, page);
+            if salt != self.salt || stored != checksum {
+                break;
+            }
+            pending.insert(page_num, offset);
+            offset += constants::WAL_FRAME_SIZE as u64;
+            if db_pages != 0 {
+                self.frames += pending.len() as u32;
+                self.index.extend(pending.drain());
+                self.db_pages = db_pages;
+                self.committed_checksum = checksum;
+                self.committed_end = offset;
+            }
+        }
+        // frames of an unfinished commit are overwritten by the next one
+        self.checksum = self.committed_checksum;
+        self.end = self.committed_end;
+        Ok(())
+    }
+
+    /// Empties the log, starting over with a new salt.
+    fn reset(&mut self) -> Result<(), Box<dyn Error>> {
+        self.salt = new_salt(self.salt);
+        let mut header = [0u8; constants::WAL_HEADER_SIZE as usize];
+        header[..constants::WAL_MAGIC.len()].copy_from_slice(constants::WAL_MAGIC);
+        header[8..12].copy_from_slice(&constants::PAGE_SIZE.to_le_bytes());
+        header[12..16].copy_from_slice(&self.salt.to_le_bytes());
+        self.file.set_size(0)?;
+        self.file.write_at(0, &header)?;
+        self.file.sync()?;
+
+        self.checksum = self.salt;
+        self.committed_checksum = self.salt;
+        self.end = constants::WAL_HEADER_SIZE as u64;
+        self.committed_end = self.end;
+        self.index.clear();
+        self.pending.clear();
+        self.db_pages = 0;
+        self.frames = 0;
+        Ok(())
+    }
+
+    /// Database size in pages as of the last commit in the log, 0 if there is none.
+    pub fn db_pages(&self) -> u32 {
+        self.db_pages
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frames
+    }
+
+    /// Latest image of `page_num` in the log, uncommitted frames of this session included.
+    pub fn read_page(&mut self, page_num: u32) -> Result<Option<Page>, Box<dyn Error>> {
+        let offset = match self.pending.get(&page_num).or_else(|| self.index.get(&page_num)) {
+            Some(offset) => *offset,
+            None => return Ok(None),
+        };
+        let mut page = vec![0u8; constants::PAGE_SIZE as usize];
+        self.file.read_at(offset + constants::WAL_FRAME_HEADER_SIZE as u64, &mut page)?;
+        Ok(Some(vec_to_page(&mut page)))
+    }
+
+    /// Appends a frame that only becomes visible to a later open once a commit follows it.
+    pub fn append(&mut self, page_num: u32, page: &Page) -> Result<(), Box<dyn Error>> {
+        self.write_frame(page_num, 0, page)
+    }
+
+    fn write_frame(&mut self, page_num: u32, db_pages: u32, page: &Page) -> Result<(), Box<dyn Error>> {
+        self.checksum = checksum_frame(self.checksum, page_num, db_pages, page);
+        let mut frame_header = [0u8; constants::WAL_FRAME_HEADER_SIZE as usize];
+        frame_header[0..4].copy_from_slice(&page_num.to_le_bytes());
+        frame_header[4..8].copy_from_slice(&db_pages.to_le_bytes());
+        frame_header[8..12].copy_from_slice(&self.salt.to_le_bytes());
+        frame_header[12..16].copy_from_slice(&self.checksum.to_le_bytes());
+        let mut frame = frame_header.to_vec();
+        frame.extend_from_slice(page);
+        self.file.write_at(self.end, &frame)?;
+        self.pending.insert(page_num, self.end);
+        self.end += constants::WAL_FRAME_SIZE as u64;
+        Ok(())
+    }
+
+    /// Appends `pages` and makes them, together with the frames appended since the last
+    /// commit, durable and visible. `db_pages` is the size of the database after the commit.
+    pub fn commit(&mut self, pages: Vec<(u32, Page)>, db_pages: u32) -> Result<(), Box<dyn Error>> {
+        let last = pages.len().saturating_sub(1);
+        for (i, (page_num, page)) in pages.iter().enumerate() {
+            self.write_frame(*page_num, if i == last { db_pages } else { 0 }, page)?;
+        }
+        self.file.sync()?;
+
+        self.frames += self.pending.len() as u32;
+        self.index.extend(self.pending.drain());
+        self.db_pages = db_pages;
+        self.committed_checksum = self.checksum;
+        self.committed_end = self.end;
+        Ok(())
+    }
+
+    /// Forgets the frames written since the last commit.
+    pub fn rollback(&mut self) {
+        self.pending.clear();
+        self.checksum = self.committed_checksum;
+        self.end = self.committed_end;
+    }
+
+    /// Copies the latest committed image of every page in the log to `db_file`, then empties
+    /// the log. A crash midway leaves the log intact, so the copy is simply redone.
+    pub fn checkpoint(&mut self, db_file: &mut dyn Storage) -> Result<(), Box<dyn Error>> {
+        if !self.pending.is_empty() {
+            return Err(Box::new(std::io::Error::other("Cannot checkpoint the log with uncommitted frames")));
+        }
+        if self.frames == 0 {
+            return Ok(());
+        }
+        let mut page_nums = self.index.keys().copied().collect::<Vec<u32>>();
+        page_nums.sort();
+        for page_num in page_nums {
+            if page_num >= self.db_pages {
+                continue;
+            }
+            let page = self.read_page(page_num)?.unwrap();
+            db_file.write_at(page_num as u64 * constants::PAGE_SIZE as u64, &page)?;
+        }
+        db_file.set_size(self.db_pages as u64 * constants::PAGE_SIZE as u64)?;
+        db_file.sync()?;
+        self.reset()
+    }
+
+    pub fn delete(self, vfs: &dyn Vfs) -> Result<(), Box<dyn Error>> {
+        vfs.remove(&self.path)
+    }
+}
+
+fn checksum_frame(seed: u32, page_num: u32, db_pages: u32, page: &[u8]) -> u32 {
+    let mut fields = [0u8; 8];
+    fields[0..4].copy_from_slice(&page_num.to_le_bytes());
+    fields[4..8].copy_from_slice(&db_pages.to_le_bytes());
+    checksum(seed, &fields, page)
+}
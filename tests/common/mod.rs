@@ -0,0 +1,33 @@
+use sqlite_rs::btree::{leaf_bytes, NodeType};
+use sqlite_rs::constants::{INTERNAL_NODE_MIN_CELLS, LEAF_NODE_MIN_BYTES, LEAF_NODE_SPACE};
+use sqlite_rs::table::Database;
+
+/// Keys of the subtree of the users table at `page_num`, checking the invariants of the
+/// tree along the way.
+pub fn collect_keys(db: &mut Database, page_num: u32, keys: &mut Vec<u32>) {
+    let node = db.pager.get_page(page_num).unwrap().clone();
+    if let NodeType::NodeLeaf { cells, .. } = &node.node_type {
+        assert!(leaf_bytes(cells) <= LEAF_NODE_SPACE);
+    }
+    if page_num != db.table("users").unwrap().root_page_num {
+        match &node.node_type {
+            NodeType::NodeInternal { cells, .. } => assert!(cells.len() as u32 >= INTERNAL_NODE_MIN_CELLS),
+            NodeType::NodeLeaf { cells, .. } => assert!(leaf_bytes(cells) >= LEAF_NODE_MIN_BYTES),
+            NodeType::NodeOverflow { .. } => panic!("Overflow page {} linked in the tree", page_num),
+        }
+    }
+    match node.node_type {
+        NodeType::NodeInternal { cells, right_child } => {
+            for child in cells.iter().map(|c| c.child).chain([right_child]) {
+                assert_eq!(db.pager.get_page(child).unwrap().parent, page_num);
+                let start = keys.len();
+                collect_keys(db, child, keys);
+                if let Some(cell) = cells.iter().find(|c| c.child == child) {
+                    assert_eq!(keys[start..].last(), Some(&cell.key));
+                }
+            }
+        },
+        NodeType::NodeLeaf { cells, .. } => keys.extend(cells.iter().map(|c| c.key)),
+        NodeType::NodeOverflow { .. } => panic!("Overflow page {} linked in the tree", page_num),
+    }
+}
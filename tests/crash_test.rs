@@ -0,0 +1,159 @@
+mod common;
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::Path;
+use std::rc::Rc;
+
+use rand::rngs::StdRng;
+use rand::seq::IteratorRandom;
+use rand::{Rng, SeedableRng};
+use sqlite_rs::storage::{MemVfs, Vfs};
+use sqlite_rs::table::{self, JournalMode, Row, Database};
+
+use common::collect_keys;
+
+const DB: &str = "crash.db";
+
+fn open(vfs: &MemVfs) -> Result<Database, Box<dyn Error>> {
+    Database::open_with_vfs(DB.to_string(), Rc::new(vfs.clone()))
+}
+
+fn table_keys(db: &mut Database) -> BTreeSet<u32> {
+    let mut keys = Vec::new();
+    let root_page_num = db.table("users").unwrap().root_page_num;
+    collect_keys(db, root_page_num, &mut keys);
+    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]), "keys out of order");
+    keys.into_iter().collect()
+}
+
+/// One transaction of random inserts and deletes, applied to `keys` as well.
+fn random_transaction(db: &mut Database, rng: &mut StdRng, keys: &mut BTreeSet<u32>) -> Result<(), Box<dyn Error>> {
+    for _ in 0..rng.gen_range(1..60) {
+        if keys.is_empty() || rng.gen_bool(0.7) {
+            let mut row = Row::new();
+            row.id = rng.gen_range(0..5_000);
+            if keys.insert(row.id) {
+                table::insert_row(db, "users", row)?;
+            }
+        } else {
+            let id = *keys.iter().choose(rng).unwrap();
+            keys.remove(&id);
+            assert!(table::delete_row(db, "users", id)?);
+        }
+    }
+    Ok(())
+}
+
+/// Runs random transactions and checkpoints until the armed failure stops the "process".
+/// Returns the keys committed so far and, if the failure hit a commit, the keys the
+/// database holds if that commit made it to disk anyway.
+fn run_until_crash(db: &mut Database, rng: &mut StdRng, mut committed: BTreeSet<u32>) -> (BTreeSet<u32>, Option<BTreeSet<u32>>) {
+    for _ in 0..200 {
+        if rng.gen_bool(0.1) {
+            if db.pager.wal_checkpoint().is_err() {
+                return (committed, None);
+            }
+            continue;
+        }
+        let mut keys = committed.clone();
+        if db.pager.begin().is_err() || random_transaction(db, rng, &mut keys).is_err() {
+            return (committed, None);
+        }
+        if db.pager.commit().is_err() {
+            return (committed, Some(keys));
+        }
+        committed = keys;
+    }
+    (committed, None)
+}
+
+fn crash_and_check(mode: JournalMode, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let vfs = MemVfs::new();
+    let mut db = open(&vfs).unwrap();
+    db.pager.set_journal_mode(mode).unwrap();
+    // a small cache writes pages out before their transaction commits
+    db.pager.set_cache_capacity(8).unwrap();
+    let mut committed = BTreeSet::new();
+    db.pager.begin().unwrap();
+    random_transaction(&mut db, &mut rng, &mut committed).unwrap();
+    db.pager.commit().unwrap();
+
+    vfs.fail_at_write(vfs.writes() + rng.gen_range(1..300));
+    let (committed, in_flight) = run_until_crash(&mut db, &mut rng, committed);
+    assert!(vfs.has_crashed(), "seed {}: the workload ended before the failure", seed);
+    drop(db);
+    vfs.crash(&mut rng);
+
+    let mut db = open(&vfs).unwrap_or_else(|e| panic!("seed {}: reopening failed: {}", seed, e));
+    assert_eq!(db.pager.journal_mode(), mode);
+    let keys = table_keys(&mut db);
+    assert!(keys == committed || Some(&keys) == in_flight.as_ref(),
+        "seed {}: the database holds neither the last commit nor the one in flight", seed);
+
+    // and it keeps working
+    let mut row = Row::new();
+    row.id = 5_000;
+    table::insert_row(&mut db, "users", row).unwrap();
+    db.close();
+    let mut db = open(&vfs).unwrap();
+    assert_eq!(table_keys(&mut db).len(), keys.len() + 1, "seed {}", seed);
+}
+
+#[test]
+fn random_crashes_with_rollback_journal_leave_a_consistent_database() {
+    for seed in 0..40 {
+        crash_and_check(JournalMode::Rollback, seed);
+    }
+}
+
+#[test]
+fn random_crashes_in_wal_mode_leave_a_consistent_database() {
+    for seed in 0..40 {
+        crash_and_check(JournalMode::Wal, seed);
+    }
+}
+
+#[test]
+fn crash_keeps_drops_or_tears_unsynced_writes() {
+    let (mut kept, mut lost, mut torn) = (false, false, false);
+    for seed in 0..50 {
+        let vfs = MemVfs::new();
+        let mut file = vfs.open(Path::new("f")).unwrap();
+        file.write_at(0, &[0xAA; 2048]).unwrap();
+        file.sync().unwrap();
+        file.write_at(0, &[0xBB; 2048]).unwrap();
+        vfs.crash(&mut StdRng::seed_from_u64(seed));
+
+        let data = vfs.read(Path::new("f")).unwrap();
+        let new = data.iter().take_while(|b| **b == 0xBB).count();
+        assert_eq!(new % 512, 0);
+        assert!(data[new..].iter().all(|b| *b == 0xAA));
+        match new {
+            2048 => kept = true,
+            0 => lost = true,
+            _ => torn = true,
+        }
+    }
+    assert!(kept && lost && torn);
+}
+
+#[test]
+fn failing_write_stops_the_process() {
+    let vfs = MemVfs::new();
+    let mut file = vfs.open(Path::new("f")).unwrap();
+    file.write_at(0, b"synced").unwrap();
+    file.sync().unwrap();
+    vfs.fail_at_write(vfs.writes() + 2);
+    file.write_at(0, b"first!").unwrap();
+    assert!(file.write_at(0, b"second").is_err());
+    assert!(vfs.has_crashed());
+    assert!(file.sync().is_err());
+    assert!(vfs.open(Path::new("g")).is_err());
+
+    vfs.crash(&mut StdRng::seed_from_u64(0));
+    let data = vfs.read(Path::new("f")).unwrap();
+    assert!(data == b"synced" || data == b"first!");
+    assert!(file.write_at(0, b"again!").is_ok());
+}
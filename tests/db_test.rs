@@ -1,76 +1,127 @@
+mod common;
+
 use std::error::Error;
+use std::rc::Rc;
+
+use rand::seq::SliceRandom;
+use sqlite_rs::btree::{get_content_len, leaf_bytes, Cell, NodeType};
+use sqlite_rs::cursor::{cursor_advance, cursor_retreat, cursor_seek, cursor_value, table_end, table_start};
+use sqlite_rs::constants::{LEAF_NODE_SPACE, ROOT_PAGE_NUM, WAL_AUTOCHECKPOINT_FRAMES};
+use sqlite_rs::record::Value;
+use sqlite_rs::storage::MmapVfs;
+use sqlite_rs::table::{self, JournalMode, Order, Database, Row, RowUpdate};
+use std::ops::Bound;
 
-use sqlite_rs::btree::get_content_len;
-use sqlite_rs::constants::ROWS_PER_PAGE;
-use sqlite_rs::table::{Table, Row};
-use sqlite_rs::sql::{prepare_statement, execute_statement, PrepareResult, ExecuteResult, Statement, StatementType};
+use common::collect_keys;
 
-fn insert_row(table: &mut Table, key: u32) -> Result<(), Box<dyn Error>> {
+use sqlite_rs::sql::{prepare_statement, execute_statement, PrepareResult, ExecuteResult, SelectQuery, Statement, StatementType};
+
+fn insert_row(db: &mut Database, key: u32) -> Result<(), Box<dyn Error>> {
     let mut statement = Statement {
         statement_type: StatementType::Insert,
         row_to_insert: Row::new(),
+        table: String::new(),
     };
     let cmd = format!("insert {} user1 user1@email.com", key);
     let prepare_result = prepare_statement(cmd.as_str(), &mut statement);
-    let execute_result = execute_statement(statement, table);
+    let execute_result = execute_statement(statement, db);
 
     if prepare_result == PrepareResult::PrepareSuccess && execute_result == ExecuteResult::ExecuteSuccess {
         Ok(())
     } else {
-        Err(Box::new(std::io::Error::new(std::io::ErrorKind::Other, "Failed to INSERT row")))
+        Err(Box::new(std::io::Error::other("Failed to INSERT row")))
+    }
+}
+
+fn text(text: &str) -> Value {
+    Value::Text(text.to_string())
+}
+
+fn temp_db(name: &str) -> String {
+    let path = std::env::temp_dir().join(name);
+    let path = path.to_str().unwrap().to_string();
+    // a journal or log left by an earlier run would be recovered into the new database
+    for suffix in ["", "-journal", "-wal"] {
+        let _ = std::fs::remove_file(format!("{}{}", path, suffix));
+    }
+    path
+}
+
+fn tree_depth(db: &mut Database, page_num: u32) -> u32 {
+    match db.pager.get_page(page_num).unwrap().node_type {
+        NodeType::NodeInternal { right_child, .. } => 1 + tree_depth(db, right_child),
+        NodeType::NodeLeaf { .. } => 1,
+        NodeType::NodeOverflow { .. } => panic!("Overflow page {} linked in the tree", page_num),
     }
 }
 
 #[test]
 fn insert_and_select() {
-    let mut table = Table::db_open("test.db".to_string());
+    let mut db = Database::open(temp_db("sqlighter_insert_and_select.db")).unwrap();
     let mut statement = Statement {
         statement_type: StatementType::Insert,
         row_to_insert: Row::new(),
+        table: String::new(),
     };
     let cmd = "insert 1 user1 user1@email.com";
     let prepare_result = prepare_statement(cmd, &mut statement);
     assert_eq!(prepare_result, PrepareResult::PrepareSuccess);
-    let execute_result = execute_statement(statement, &mut table);
+    let execute_result = execute_statement(statement, &mut db);
     assert_eq!(execute_result, ExecuteResult::ExecuteSuccess);
-    assert_eq!(table.pager.pages.len(), 1);
+    // the system catalog and the root leaf
+    assert_eq!(db.pager.pages.len(), 2);
 
     let mut statement = Statement {
         statement_type: StatementType::Select,
         row_to_insert: Row::new(),
+        table: String::new(),
     };
     let cmd = "select";
     let prepare_result = prepare_statement(cmd, &mut statement);
     assert_eq!(prepare_result, PrepareResult::PrepareSuccess);
-    let execute_result = execute_statement(statement, &mut table);
+    let execute_result = execute_statement(statement, &mut db);
     assert_eq!(execute_result, ExecuteResult::ExecuteSuccess);
 }
 
 #[test]
 fn insert_max_rows() {
 
-    let mut table = Table::db_open("test.db".to_string());
-    for i in 0..ROWS_PER_PAGE {
-        insert_row(&mut table, i).unwrap();
+    let mut db = Database::open(temp_db("sqlighter_insert_max_rows.db")).unwrap();
+    // the root leaf takes rows until their cells no longer fit in its page
+    let mut rows = 0;
+    loop {
+        let root = db.pager.get_page(ROOT_PAGE_NUM).unwrap().clone();
+        match &root.node_type {
+            NodeType::NodeLeaf { cells, .. } => assert!(leaf_bytes(cells) <= LEAF_NODE_SPACE),
+            _ => break,
+        }
+        assert_eq!(get_content_len(root.to_page()), rows);
+        insert_row(&mut db, rows).unwrap();
+        rows += 1;
     }
-    let root = table.pager.get_page(0).unwrap();
-    assert_eq!(get_content_len(root.clone().to_page()), ROWS_PER_PAGE);
+    // a row of the short insert form takes 30 bytes of the page
+    assert_eq!(rows, LEAF_NODE_SPACE / 30 + 1);
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, (0..rows).collect::<Vec<u32>>());
 
 } 
 
 #[test]
 fn insert_max_len_strings() {
-    let mut table = Table::db_open("test.db".to_string());
+    let mut db = Database::open(temp_db("sqlighter_insert_max_len_strings.db")).unwrap();
     let mut statement = Statement {
         statement_type: StatementType::Insert,
         row_to_insert: Row::new(),
+        table: String::new(),
     };
     let cmd = format!("insert 1 {} {}", "a".repeat(32), "b".repeat(255));
     let prepare_result = prepare_statement(cmd.as_str(), &mut statement);
     assert_eq!(prepare_result, PrepareResult::PrepareSuccess);
-    let execute_result = execute_statement(statement, &mut table);
+    let execute_result = execute_statement(statement, &mut db);
     assert_eq!(execute_result, ExecuteResult::ExecuteSuccess);
-    assert_eq!(table.pager.pages.len(), 1);
+    // the system catalog and the root leaf
+    assert_eq!(db.pager.pages.len(), 2);
 }
 
 #[test]
@@ -78,6 +129,7 @@ fn test_overflow_string_insert() {
     let mut statement = Statement {
         statement_type: StatementType::Insert,
         row_to_insert: Row::new(),
+        table: String::new(),
     };
     let cmd = format!("insert 1 {} {}", "a".repeat(33), "b".repeat(256));
     let prepare_result = prepare_statement(cmd.as_str(), &mut statement);
@@ -89,6 +141,7 @@ fn test_negative_id_insert() {
     let mut statement = Statement {
         statement_type: StatementType::Insert,
         row_to_insert: Row::new(),
+        table: String::new(),
     };
     let cmd = "insert -1 user1 user@email.com";
     let prepare_result = prepare_statement(cmd, &mut statement);
@@ -97,26 +150,993 @@ fn test_negative_id_insert() {
 
 #[test]
 fn test_duplicate_key_insert() {
-    let mut table = Table::db_open("test.db".to_string());
+    let mut db = Database::open(temp_db("sqlighter_test_duplicate_key_insert.db")).unwrap();
     let mut statement = Statement {
         statement_type: StatementType::Insert,
         row_to_insert: Row::new(),
+        table: String::new(),
     };
-    let cmd = format!("insert 1 user1 user1");
+    let cmd = "insert 1 user1 user1".to_string();
     let prepare_result = prepare_statement(cmd.as_str(), &mut statement);
     assert_eq!(prepare_result, PrepareResult::PrepareSuccess);
-    let execute_result = execute_statement(statement, &mut table);
+    let execute_result = execute_statement(statement, &mut db);
     assert_eq!(execute_result, ExecuteResult::ExecuteSuccess);
 
     let mut statement = Statement {
         statement_type: StatementType::Insert,
         row_to_insert: Row::new(),
+        table: String::new(),
     };
     
-    let cmd = format!("insert 1 user1 user1");
+    let cmd = "insert 1 user1 user1".to_string();
     let prepare_result = prepare_statement(cmd.as_str(), &mut statement);
     assert_eq!(prepare_result, PrepareResult::PrepareSuccess);
-    let execute_result = execute_statement(statement, &mut table);
+    let execute_result = execute_statement(statement, &mut db);
     assert_eq!(execute_result, ExecuteResult::ExecuteFailure("Key already exists".to_string()));
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn insert_splits_leaves_and_grows_tree() {
+    let path = temp_db("sqlighter_split.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    let mut ids: Vec<u32> = (0..20_000).collect();
+    ids.shuffle(&mut rand::thread_rng());
+    for id in &ids {
+        let mut row = Row::new();
+        row.id = *id;
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    assert_eq!(db.table("users").unwrap().root_page_num, ROOT_PAGE_NUM);
+    assert!(matches!(db.pager.get_page(ROOT_PAGE_NUM).unwrap().node_type, NodeType::NodeInternal { .. }));
+
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, (0..20_000).collect::<Vec<u32>>());
+
+    let mut row = Row::new();
+    row.id = 12_345;
+    assert!(table::insert_row(&mut db, "users", row).is_err());
+    db.close();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, (0..20_000).collect::<Vec<u32>>());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn find_rows_in_deep_tree() {
+    let path = temp_db("sqlighter_find.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    for id in (0..40_000).step_by(2) {
+        let mut row = Row::new();
+        row.id = id;
+        row.fields = vec![text("user"), text(&"e".repeat(255))];
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    assert!(tree_depth(&mut db, ROOT_PAGE_NUM) >= 3);
+
+    for id in 0..40_000 {
+        let found = table::find_row(&mut db, "users", id).unwrap();
+        if id % 2 == 0 {
+            let row = found.unwrap();
+            assert_eq!(row.id, id);
+            assert_eq!(row.fields[0], text("user"));
+        } else {
+            assert!(found.is_none());
+        }
+    }
+    assert!(table::find_row(&mut db, "users", 50_000).unwrap().is_none());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn rows_of_varying_sizes_fill_leaves_by_bytes() {
+    let path = temp_db("sqlighter_varying.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    let mut ids: Vec<u32> = (0..5_000).collect();
+    ids.shuffle(&mut rand::thread_rng());
+    let email = |id: u32| text(&"e".repeat((id as usize * 37) % 256));
+    for id in &ids {
+        let mut row = Row::new();
+        row.id = *id;
+        row.fields = vec![text("u"), email(*id)];
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+
+    // growing and shrinking rows in place splits and merges their leaves
+    let update = |email: &str| RowUpdate { id: None, fields: vec![(1, text(email))] };
+    assert_eq!(table::update_rows(&mut db, "users", (Bound::Included(1_000), Bound::Excluded(2_000)), &update(&"e".repeat(255))).unwrap(), 1_000);
+    assert_eq!(table::update_rows(&mut db, "users", (Bound::Included(3_000), Bound::Excluded(5_000)), &update("")).unwrap(), 2_000);
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, (0..5_000).collect::<Vec<u32>>());
+
+    let (deleted, kept) = ids.split_at(3_000);
+    for id in deleted {
+        assert!(table::delete_row(&mut db, "users", *id).unwrap());
+    }
+    db.close();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    let mut expected = kept.to_vec();
+    expected.sort();
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, expected);
+    for row in table::select_all_rows(&mut db, "users").unwrap() {
+        let expected = match row.id {
+            1_000..2_000 => text(&"e".repeat(255)),
+            3_000..5_000 => text(""),
+            id => email(id),
+        };
+        assert_eq!(row.fields[1], expected);
+    }
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn prepare_delete() {
+    let mut statement = Statement {
+        statement_type: StatementType::Select,
+        row_to_insert: Row::new(),
+        table: String::new(),
+    };
+    assert_eq!(prepare_statement("delete from users where id = 5", &mut statement), PrepareResult::PrepareSuccess);
+    assert!(matches!(statement.statement_type, StatementType::Delete((Bound::Included(5), Bound::Included(5)))));
+
+    assert_eq!(prepare_statement("delete from users where id >= 3 and id < 10 and id > 4", &mut statement), PrepareResult::PrepareSuccess);
+    assert!(matches!(statement.statement_type, StatementType::Delete((Bound::Excluded(4), Bound::Excluded(10)))));
+
+    assert_eq!(prepare_statement("delete from users where id = -1", &mut statement), PrepareResult::PrepareNegativeID);
+    assert_eq!(prepare_statement("delete from orders where id = 1", &mut statement), PrepareResult::PrepareSyntaxError);
+    assert_eq!(prepare_statement("delete from users where id = 1 or id = 2", &mut statement), PrepareResult::PrepareSyntaxError);
+}
+
+#[test]
+fn delete_rebalances_and_collapses_tree() {
+    let path = temp_db("sqlighter_delete.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    let mut ids: Vec<u32> = (0..20_000).collect();
+    ids.shuffle(&mut rand::thread_rng());
+    for id in &ids {
+        let mut row = Row::new();
+        row.id = *id;
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+
+    let (deleted, kept) = ids.split_at(12_000);
+    for id in deleted {
+        assert!(table::delete_row(&mut db, "users", *id).unwrap());
+    }
+    assert!(!table::delete_row(&mut db, "users", deleted[0]).unwrap());
+
+    let mut expected = kept.to_vec();
+    expected.sort();
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, expected);
+    db.close();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, expected);
+
+    assert_eq!(table::delete_rows(&mut db, "users", (Bound::Unbounded, Bound::Unbounded)).unwrap(), 8_000);
+    assert_eq!(tree_depth(&mut db, ROOT_PAGE_NUM), 1);
+    assert_eq!(db.pager.get_page(ROOT_PAGE_NUM).unwrap().content_len, 0);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn delete_range_statement() {
+    let path = temp_db("sqlighter_delete_range.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    for i in 0..100 {
+        insert_row(&mut db, i).unwrap();
+    }
+
+    let mut statement = Statement {
+        statement_type: StatementType::Select,
+        row_to_insert: Row::new(),
+        table: String::new(),
+    };
+    assert_eq!(prepare_statement("delete from users where id >= 10 and id < 90", &mut statement), PrepareResult::PrepareSuccess);
+    assert_eq!(execute_statement(statement, &mut db), ExecuteResult::ExecuteSuccess);
+
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, (0..10).chain(90..100).collect::<Vec<u32>>());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn delete_range_after_a_leaf_is_emptied() {
+    let path = temp_db("sqlighter_delete_emptied.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    for id in 0..1_000 {
+        let mut row = Row::new();
+        row.id = id;
+        row.fields = vec![text("u"), text(&"e".repeat(100))];
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    let (left, middle) = match &db.pager.get_page(ROOT_PAGE_NUM).unwrap().node_type {
+        NodeType::NodeInternal { cells, .. } => (cells[0], cells[1]),
+        _ => panic!("Expected an internal root"),
+    };
+
+    // a leaf left with a single cell too big to underflow is emptied by its next delete:
+    // it merges with its left sibling, whose max key is below the middle leaf's
+    let fat = Cell { key: middle.key, record_size: 1_100, record: vec![0; 1_100], overflow_page: 0 };
+    let node = db.pager.get_page_mut(middle.child).unwrap();
+    node.node_type = NodeType::NodeLeaf { cells: vec![fat], next_leaf: match node.node_type {
+        NodeType::NodeLeaf { next_leaf, .. } => next_leaf,
+        _ => panic!("Expected a leaf"),
+    } };
+    node.content_len = 1;
+    assert!(table::delete_row(&mut db, "users", middle.key).unwrap());
+
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, (0..=left.key).chain(middle.key + 1..1_000).collect::<Vec<u32>>());
+    // the range starts between the merged leaf's max and the middle leaf's old key
+    let deleted = table::delete_rows(&mut db, "users", (Bound::Excluded(left.key), Bound::Unbounded)).unwrap();
+    assert_eq!(deleted, 1_000 - middle.key - 1);
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, (0..=left.key).collect::<Vec<u32>>());
+    std::fs::remove_file(path).unwrap();
+}
+
+fn run(db: &mut Database, cmd: &str) -> ExecuteResult {
+    let mut statement = Statement {
+        statement_type: StatementType::Select,
+        row_to_insert: Row::new(),
+        table: String::new(),
+    };
+    assert_eq!(prepare_statement(cmd, &mut statement), PrepareResult::PrepareSuccess);
+    execute_statement(statement, db)
+}
+
+#[test]
+fn update_rows_in_place() {
+    let path = temp_db("sqlighter_update.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    for i in 0..50 {
+        insert_row(&mut db, i).unwrap();
+    }
+
+    assert_eq!(run(&mut db, "update users set email = 'new@email.com' where id = 7"), ExecuteResult::ExecuteSuccess);
+    let row = table::find_row(&mut db, "users", 7).unwrap().unwrap();
+    assert_eq!(row.fields, vec![text("user1"), text("new@email.com")]);
+
+    assert_eq!(run(&mut db, "update users set username = bob, email = bob@email.com where id >= 40"), ExecuteResult::ExecuteSuccess);
+    for id in 40..50 {
+        let row = table::find_row(&mut db, "users", id).unwrap().unwrap();
+        assert_eq!(row.fields, vec![text("bob"), text("bob@email.com")]);
+    }
+    assert_eq!(table::find_row(&mut db, "users", 39).unwrap().unwrap().fields[0], text("user1"));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn update_primary_key_moves_row() {
+    let path = temp_db("sqlighter_update_key.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    for i in 0..50 {
+        insert_row(&mut db, i).unwrap();
+    }
+
+    assert_eq!(run(&mut db, "update users set id = 1000, username = moved where id = 3"), ExecuteResult::ExecuteSuccess);
+    assert!(table::find_row(&mut db, "users", 3).unwrap().is_none());
+    let row = table::find_row(&mut db, "users", 1000).unwrap().unwrap();
+    assert_eq!(row.id, 1000);
+    assert_eq!(row.fields[0], text("moved"));
+
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys.len(), 50);
+    assert_eq!(keys.last(), Some(&1000));
+
+    assert_eq!(run(&mut db, "update users set id = 5 where id = 4"), ExecuteResult::ExecuteFailure("Key already exists".to_string()));
+    assert!(table::find_row(&mut db, "users", 4).unwrap().is_some());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn prepare_update_errors() {
+    let mut statement = Statement {
+        statement_type: StatementType::Select,
+        row_to_insert: Row::new(),
+        table: String::new(),
+    };
+    let cmd = format!("update users set username = {} where id = 1", "a".repeat(33));
+    assert_eq!(prepare_statement(cmd.as_str(), &mut statement), PrepareResult::PrepareStringTooLong);
+    assert_eq!(prepare_statement("update users set id = -2 where id = 1", &mut statement), PrepareResult::PrepareNegativeID);
+    assert_eq!(prepare_statement("update users set age = 3 where id = 1", &mut statement), PrepareResult::PrepareSyntaxError);
+    assert_eq!(prepare_statement("update users email = x where id = 1", &mut statement), PrepareResult::PrepareSyntaxError);
+}
+
+#[test]
+fn select_scans_every_leaf_in_order() {
+    let path = temp_db("sqlighter_scan.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    let mut ids: Vec<u32> = (0..10_000).collect();
+    ids.shuffle(&mut rand::thread_rng());
+    for id in &ids {
+        let mut row = Row::new();
+        row.id = *id;
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    let rows = table::select_all_rows(&mut db, "users").unwrap();
+    assert_eq!(rows.iter().map(|r| r.id).collect::<Vec<u32>>(), (0..10_000).collect::<Vec<u32>>());
+
+    for id in (0..10_000).step_by(3) {
+        table::delete_row(&mut db, "users", id).unwrap();
+    }
+    let expected = (0..10_000).filter(|id| id % 3 != 0).collect::<Vec<u32>>();
+    let rows = table::select_all_rows(&mut db, "users").unwrap();
+    assert_eq!(rows.iter().map(|r| r.id).collect::<Vec<u32>>(), expected);
+    db.close();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    let rows = table::select_all_rows(&mut db, "users").unwrap();
+    assert_eq!(rows.iter().map(|r| r.id).collect::<Vec<u32>>(), expected);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn seek_and_range_scan() {
+    let path = temp_db("sqlighter_range.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    let mut ids: Vec<u32> = (0..10_000).map(|i| i * 10).collect();
+    ids.shuffle(&mut rand::thread_rng());
+    for id in &ids {
+        let mut row = Row::new();
+        row.id = *id;
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+
+    let mut cursor = table_start(&mut db.pager, ROOT_PAGE_NUM).unwrap();
+    cursor_seek(&mut cursor, 12_345).unwrap();
+    assert_eq!(cursor_value(&mut cursor).unwrap().key, 12_350);
+    cursor_advance(&mut cursor);
+    assert_eq!(cursor_value(&mut cursor).unwrap().key, 12_360);
+    cursor_seek(&mut cursor, 99_991).unwrap();
+    assert!(cursor.end_of_table);
+    drop(cursor);
+
+    let ids = |rows: Vec<Row>| rows.iter().map(|r| r.id).collect::<Vec<u32>>();
+    let rows = table::select_rows(&mut db, "users", (Bound::Included(500), Bound::Excluded(1_000)), Order::Ascending, None).unwrap();
+    assert_eq!(ids(rows), (50..100).map(|i| i * 10).collect::<Vec<u32>>());
+    let rows = table::select_rows(&mut db, "users", (Bound::Excluded(500), Bound::Included(1_000)), Order::Ascending, None).unwrap();
+    assert_eq!(ids(rows), (51..101).map(|i| i * 10).collect::<Vec<u32>>());
+    let rows = table::select_rows(&mut db, "users", (Bound::Included(99_980), Bound::Unbounded), Order::Ascending, None).unwrap();
+    assert_eq!(ids(rows), vec![99_980, 99_990]);
+    let rows = table::select_rows(&mut db, "users", (Bound::Included(501), Bound::Excluded(509)), Order::Ascending, None).unwrap();
+    assert!(rows.is_empty());
+    let rows = table::select_rows(&mut db, "users", (Bound::Excluded(u32::MAX), Bound::Unbounded), Order::Ascending, None).unwrap();
+    assert!(rows.is_empty());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn prepare_select_where() {
+    let mut statement = Statement {
+        statement_type: StatementType::Insert,
+        row_to_insert: Row::new(),
+        table: String::new(),
+    };
+    assert_eq!(prepare_statement("select * from users where id >= 3 and id < 7", &mut statement), PrepareResult::PrepareSuccess);
+    assert!(matches!(statement.statement_type, StatementType::SelectQuery(SelectQuery { range: (Bound::Included(3), Bound::Excluded(7)), order: Order::Ascending, limit: None })));
+    assert_eq!(prepare_statement("select * from users", &mut statement), PrepareResult::PrepareSuccess);
+    assert!(matches!(statement.statement_type, StatementType::SelectQuery(SelectQuery { range: (Bound::Unbounded, Bound::Unbounded), .. })));
+    assert_eq!(prepare_statement("select * from users where id < 100 order by id desc limit 10", &mut statement), PrepareResult::PrepareSuccess);
+    assert!(matches!(statement.statement_type, StatementType::SelectQuery(SelectQuery { range: (Bound::Unbounded, Bound::Excluded(100)), order: Order::Descending, limit: Some(10) })));
+    assert_eq!(prepare_statement("select * from users order by id asc limit 3", &mut statement), PrepareResult::PrepareSuccess);
+    assert!(matches!(statement.statement_type, StatementType::SelectQuery(SelectQuery { order: Order::Ascending, limit: Some(3), .. })));
+    assert_eq!(prepare_statement("select * from users order by name", &mut statement), PrepareResult::PrepareSyntaxError);
+    assert_eq!(prepare_statement("select * from users limit 0", &mut statement), PrepareResult::PrepareSuccess);
+    assert!(matches!(statement.statement_type, StatementType::SelectQuery(SelectQuery { limit: Some(0), .. })));
+    assert_eq!(prepare_statement("select * from users limit -1", &mut statement), PrepareResult::PrepareSyntaxError);
+    assert_eq!(prepare_statement("select * from users where name = 3", &mut statement), PrepareResult::PrepareSyntaxError);
+    assert_eq!(prepare_statement("select id", &mut statement), PrepareResult::PrepareSyntaxError);
+}
+
+#[test]
+fn reverse_scan_and_descending_select() {
+    let path = temp_db("sqlighter_reverse.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    assert!(table_end(&mut db.pager, ROOT_PAGE_NUM).unwrap().end_of_table);
+
+    let mut ids: Vec<u32> = (0..10_000).map(|i| i * 2).collect();
+    ids.shuffle(&mut rand::thread_rng());
+    for id in &ids {
+        let mut row = Row::new();
+        row.id = *id;
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+
+    let mut cursor = table_end(&mut db.pager, ROOT_PAGE_NUM).unwrap();
+    let mut keys = Vec::new();
+    while !cursor.end_of_table {
+        keys.push(cursor_value(&mut cursor).unwrap().key);
+        cursor_retreat(&mut cursor);
+    }
+    drop(cursor);
+    assert_eq!(keys, (0..10_000).rev().map(|i| i * 2).collect::<Vec<u32>>());
+
+    let ids = |rows: Vec<Row>| rows.iter().map(|r| r.id).collect::<Vec<u32>>();
+    let rows = table::select_rows(&mut db, "users", (Bound::Unbounded, Bound::Unbounded), Order::Descending, Some(10)).unwrap();
+    assert_eq!(ids(rows), (9_990..10_000).rev().map(|i| i * 2).collect::<Vec<u32>>());
+    let rows = table::select_rows(&mut db, "users", (Bound::Excluded(100), Bound::Included(110)), Order::Descending, None).unwrap();
+    assert_eq!(ids(rows), vec![110, 108, 106, 104, 102]);
+    let rows = table::select_rows(&mut db, "users", (Bound::Included(100), Bound::Excluded(109)), Order::Descending, Some(2)).unwrap();
+    assert_eq!(ids(rows), vec![108, 106]);
+    let rows = table::select_rows(&mut db, "users", (Bound::Unbounded, Bound::Excluded(5)), Order::Descending, None).unwrap();
+    assert_eq!(ids(rows), vec![4, 2, 0]);
+    let rows = table::select_rows(&mut db, "users", (Bound::Included(0), Bound::Unbounded), Order::Ascending, Some(3)).unwrap();
+    assert_eq!(ids(rows), vec![0, 2, 4]);
+
+    assert_eq!(run(&mut db, "select * from users order by id desc limit 10"), ExecuteResult::ExecuteSuccess);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn reverse_scan_skips_an_empty_leaf() {
+    let path = temp_db("sqlighter_reverse_empty.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    for id in 0..1_000 {
+        let mut row = Row::new();
+        row.id = id;
+        row.fields = vec![text("u"), text(&"e".repeat(100))];
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    let (left, middle) = match &db.pager.get_page(ROOT_PAGE_NUM).unwrap().node_type {
+        NodeType::NodeInternal { cells, .. } => (cells[0], cells[1]),
+        _ => panic!("Expected an internal root"),
+    };
+
+    // the cursor crosses from the leaf after the middle one into an emptied middle leaf
+    let node = db.pager.get_page_mut(middle.child).unwrap();
+    if let NodeType::NodeLeaf { cells, .. } = &mut node.node_type {
+        cells.clear();
+    }
+    node.content_len = 0;
+
+    let mut cursor = table_end(&mut db.pager, ROOT_PAGE_NUM).unwrap();
+    let mut keys = Vec::new();
+    while !cursor.end_of_table {
+        keys.push(cursor_value(&mut cursor).unwrap().key);
+        cursor_retreat(&mut cursor);
+    }
+    drop(cursor);
+    assert_eq!(keys, (0..=left.key).chain(middle.key + 1..1_000).rev().collect::<Vec<u32>>());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn free_pages_are_persisted_and_reused() {
+    let path = temp_db("sqlighter_freelist.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    for id in 0..20_000 {
+        let mut row = Row::new();
+        row.id = id;
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    db.close();
+    let file_length = std::fs::metadata(&path).unwrap().len();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(db.pager.free_page_count(), 0);
+    table::delete_rows(&mut db, "users", (Bound::Unbounded, Bound::Unbounded)).unwrap();
+    let free_pages = db.pager.free_page_count();
+    // every page but the header, the catalog and the root, spread over several trunk pages
+    assert_eq!(free_pages as u64, file_length / 4096 - 3);
+    db.close();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(db.pager.free_page_count(), free_pages);
+    for id in 0..20_000 {
+        let mut row = Row::new();
+        row.id = id;
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    assert_eq!(db.pager.free_page_count(), 0);
+    db.close();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), file_length);
+
+    let mut db = Database::open(path.clone()).unwrap();
+    let rows = table::select_all_rows(&mut db, "users").unwrap();
+    assert_eq!(rows.len(), 20_000);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn vacuum_compacts_file() {
+    let path = temp_db("sqlighter_vacuum.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(run(&mut db, "vacuum"), ExecuteResult::ExecuteSuccess);
+    assert!(table::select_all_rows(&mut db, "users").unwrap().is_empty());
+
+    let mut ids: Vec<u32> = (0..20_000).collect();
+    ids.shuffle(&mut rand::thread_rng());
+    for id in &ids {
+        let mut row = Row::new();
+        row.id = *id;
+        row.fields = vec![text(""), text("a@b")];
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    for id in &ids[..15_000] {
+        table::delete_row(&mut db, "users", *id).unwrap();
+    }
+    let mut expected = ids[15_000..].to_vec();
+    expected.sort();
+    db.close();
+    let old_length = std::fs::metadata(&path).unwrap().len();
+    // left behind by a vacuum cut short
+    let leftovers = ["-vacuum", "-vacuum-journal"].map(|suffix| format!("{}{}", path, suffix));
+    for leftover in &leftovers {
+        std::fs::write(leftover, vec![7; 4096]).unwrap();
+    }
+
+    let reclaimed = db.vacuum().unwrap();
+    assert!(leftovers.iter().all(|leftover| !std::path::Path::new(leftover).exists()));
+    let new_length = std::fs::metadata(&path).unwrap().len();
+    assert!(reclaimed > 0);
+    assert_eq!(old_length - reclaimed, new_length);
+    assert_eq!(db.pager.free_page_count(), 0);
+    // 5000 rows of 13 bytes in full leaves, plus internal nodes and the header
+    assert!(new_length <= 4096 * (5_000 / (LEAF_NODE_SPACE as u64 / 13) + 4));
+
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, expected);
+    let rows = table::select_all_rows(&mut db, "users").unwrap();
+    assert_eq!(rows.iter().map(|r| r.id).collect::<Vec<u32>>(), expected);
+    assert!(rows.iter().all(|r| r.fields[1] == text("a@b")));
+
+    let mut row = Row::new();
+    row.id = ids[0];
+    table::insert_row(&mut db, "users", row).unwrap();
+    db.close();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(table::select_all_rows(&mut db, "users").unwrap().len(), 5_001);
+    for id in 20_000..40_000 {
+        let mut row = Row::new();
+        row.id = id;
+        row.fields = vec![text(""), text(&"e".repeat(255))];
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    // rows are streamed from the old tree to the new one, so a small cache is enough
+    db.pager.set_cache_capacity(8).unwrap();
+    db.vacuum().unwrap();
+    assert_eq!(tree_depth(&mut db, ROOT_PAGE_NUM), 3);
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys.len(), 25_001);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn file_header_is_validated_on_open() {
+    let path = temp_db("sqlighter_header.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    insert_row(&mut db, 1).unwrap();
+    db.close();
+    let counter = db.pager.change_counter();
+    insert_row(&mut db, 2).unwrap();
+    db.close();
+    assert_eq!(db.pager.change_counter(), counter + 1);
+
+    let bytes = std::fs::read(&path).unwrap();
+    assert_eq!(&bytes[..16], b"SQLighter db\0\0\0\0");
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(db.table("users").unwrap().root_page_num, ROOT_PAGE_NUM);
+    assert_eq!(db.pager.change_counter(), counter + 1);
+    assert_eq!(table::select_all_rows(&mut db, "users").unwrap().len(), 2);
+
+    let open_error = |bytes: &[u8]| {
+        std::fs::write(&path, bytes).unwrap();
+        Database::open(path.clone()).err().unwrap().to_string()
+    };
+    assert!(open_error(&[7; 8192]).contains("not a SQLighter database"));
+
+    let mut future = bytes.clone();
+    future[16..20].copy_from_slice(&99u32.to_le_bytes());
+    assert!(open_error(&future).contains("newer than the supported version"));
+
+    let mut page_size = bytes.clone();
+    page_size[20..24].copy_from_slice(&1024u32.to_le_bytes());
+    assert!(open_error(&page_size).contains("page size"));
+
+    assert!(open_error(&bytes[..100]).contains("whole number of pages"));
+
+    // a corrupt tree page fails the scans reaching it instead of panicking
+    let mut corrupt = bytes.clone();
+    corrupt[ROOT_PAGE_NUM as usize * 4096] = 9;
+    std::fs::write(&path, &corrupt).unwrap();
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(table::select_all_rows(&mut db, "users").unwrap_err().to_string(),
+        "Error loading page from disk. Probably fetching not existing page number.");
+    assert!(db.vacuum().is_err());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn page_cache_is_bounded_and_keeps_pinned_pages() {
+    let path = temp_db("sqlighter_cache.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    db.pager.set_cache_capacity(8).unwrap();
+    let mut ids: Vec<u32> = (0..20_000).collect();
+    ids.shuffle(&mut rand::thread_rng());
+    for id in &ids {
+        let mut row = Row::new();
+        row.id = *id;
+        table::insert_row(&mut db, "users", row).unwrap();
+        assert!(db.pager.pages.len() <= 8);
+    }
+    for id in &ids[..10_000] {
+        table::delete_row(&mut db, "users", *id).unwrap();
+    }
+    assert!(db.pager.pages.len() <= 8);
+
+    // evicted pages were written back: the tree is intact from the cache and after reopening
+    let mut expected = ids[10_000..].to_vec();
+    expected.sort();
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, expected);
+    db.close();
+    let mut db = Database::open(path.clone()).unwrap();
+    db.pager.set_cache_capacity(2).unwrap();
+    let rows = table::select_all_rows(&mut db, "users").unwrap();
+    assert_eq!(rows.iter().map(|r| r.id).collect::<Vec<u32>>(), expected);
+
+    let mut cursor = table_start(&mut db.pager, ROOT_PAGE_NUM).unwrap();
+    let first = cursor_value(&mut cursor).unwrap().key;
+    let page_num = cursor.page_num;
+    assert!(cursor.pager.pages.is_pinned(page_num));
+    // a second cursor over every leaf, through a cache of two pages
+    let mut other = table_start(&mut *cursor.pager, ROOT_PAGE_NUM).unwrap();
+    while !other.end_of_table {
+        cursor_advance(&mut other);
+    }
+    drop(other);
+    assert!(cursor.pager.pages.contains(page_num));
+    assert_eq!(cursor_value(&mut cursor).unwrap().key, first);
+    drop(cursor);
+    assert!(!db.pager.pages.is_pinned(page_num));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn checkpoint_writes_only_modified_pages() {
+    let path = temp_db("sqlighter_checkpoint.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    for id in 0..1_000 {
+        insert_row(&mut db, id).unwrap();
+    }
+    db.checkpoint().unwrap();
+    let counter = db.pager.change_counter();
+    let bytes = std::fs::read(&path).unwrap();
+    assert_eq!(db.pager.pages.iter().filter(|(_, page)| page.dirty).count(), 0);
+
+    // reads leave the file alone
+    assert_eq!(table::select_all_rows(&mut db, "users").unwrap().len(), 1_000);
+    assert_eq!(run(&mut db, "select * from users where id >= 500 order by id desc limit 3"), ExecuteResult::ExecuteSuccess);
+    db.checkpoint().unwrap();
+    assert_eq!(db.pager.change_counter(), counter);
+    assert_eq!(std::fs::read(&path).unwrap(), bytes);
+
+    // changes made outside of statements are not autocommitted
+    let update = RowUpdate { fields: vec![(0, text("carol"))], ..RowUpdate::default() };
+    table::update_rows(&mut db, "users", (Bound::Included(10), Bound::Included(10)), &update).unwrap();
+    assert_eq!(db.pager.pages.iter().filter(|(_, page)| page.dirty).count(), 1);
+    db.checkpoint().unwrap();
+    assert_eq!(db.pager.change_counter(), counter + 1);
+    let changed = std::fs::read(&path).unwrap();
+    let changed_pages = (0..bytes.len() / 4096)
+        .filter(|i| bytes[i * 4096..(i + 1) * 4096] != changed[i * 4096..(i + 1) * 4096])
+        .count();
+    // the header (change counter) and the leaf holding the row
+    assert_eq!(changed_pages, 2);
+
+    // the checkpointed state survives without closing the table
+    let mut reopened = Database::open(path.clone()).unwrap();
+    let row = table::find_row(&mut reopened, "users", 10).unwrap().unwrap();
+    assert_eq!(row.fields[0], text("carol"));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn hot_journal_is_rolled_back_on_open() {
+    let path = temp_db("sqlighter_journal.db");
+    let journal = format!("{}-journal", path);
+    let mut db = Database::open(path.clone()).unwrap();
+    for id in 0..5_000 {
+        let mut row = Row::new();
+        row.id = id;
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    db.close();
+    assert!(!std::path::Path::new(&journal).exists());
+    let committed = std::fs::read(&path).unwrap();
+
+    // a small cache makes the pager write pages to the file before the commit
+    let mut db = Database::open(path.clone()).unwrap();
+    db.pager.set_cache_capacity(4).unwrap();
+    assert_eq!(run(&mut db, "begin"), ExecuteResult::ExecuteSuccess);
+    for id in 0..2_500 {
+        table::delete_row(&mut db, "users", id * 2).unwrap();
+    }
+    for id in 5_000..15_000 {
+        insert_row(&mut db, id).unwrap();
+    }
+    // the process dies before committing
+    drop(db);
+    assert!(std::path::Path::new(&journal).exists());
+    assert_ne!(std::fs::read(&path).unwrap(), committed);
+
+    // a torn record at the end of the journal is ignored
+    let mut data = std::fs::read(&journal).unwrap();
+    data.extend_from_slice(&[0xAB; 1000]);
+    std::fs::write(&journal, data).unwrap();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    assert!(!std::path::Path::new(&journal).exists());
+    assert_eq!(std::fs::read(&path).unwrap(), committed);
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, (0..5_000).collect::<Vec<u32>>());
+
+    // a journal without a complete header never had pages written over
+    std::fs::write(&journal, b"SQL").unwrap();
+    let db = Database::open(path.clone()).unwrap();
+    drop(db);
+    assert!(!std::path::Path::new(&journal).exists());
+    assert_eq!(std::fs::read(&path).unwrap(), committed);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn wal_mode_commits_to_the_log_and_checkpoints() {
+    let path = temp_db("sqlighter_wal.db");
+    let wal = format!("{}-wal", path);
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(run(&mut db, "pragma journal_mode = wal"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(db.pager.journal_mode(), JournalMode::Wal);
+    let db_length = std::fs::metadata(&path).unwrap().len();
+    assert_eq!(run(&mut db, "begin"), ExecuteResult::ExecuteSuccess);
+    for id in 0..2_000 {
+        insert_row(&mut db, id).unwrap();
+    }
+    assert_eq!(run(&mut db, "commit"), ExecuteResult::ExecuteSuccess);
+    // commits only append to the log
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), db_length);
+    assert!(db.pager.wal_frame_count() > 0);
+
+    // uncommitted pages spilled to the log by a small cache are lost in a crash
+    db.pager.set_cache_capacity(4).unwrap();
+    assert_eq!(run(&mut db, "begin"), ExecuteResult::ExecuteSuccess);
+    for id in 2_000..6_000 {
+        insert_row(&mut db, id).unwrap();
+    }
+    drop(db);
+    let mut data = std::fs::read(&wal).unwrap();
+    data.extend_from_slice(&[0xCD; 5000]);
+    std::fs::write(&wal, data).unwrap();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(db.pager.journal_mode(), JournalMode::Wal);
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, (0..2_000).collect::<Vec<u32>>());
+
+    for id in 2_000..3_000 {
+        insert_row(&mut db, id).unwrap();
+    }
+    assert_eq!(run(&mut db, "pragma wal_checkpoint"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(db.pager.wal_frame_count(), 0);
+    // the database file alone now holds every row
+    let copy = temp_db("sqlighter_wal_copy.db");
+    std::fs::copy(&path, &copy).unwrap();
+    let mut copied = Database::open(copy.clone()).unwrap();
+    assert_eq!(table::select_all_rows(&mut copied, "users").unwrap().len(), 3_000);
+
+    // frequent commits are copied back once the log grows past the threshold
+    for id in 3_000..6_000 {
+        insert_row(&mut db, id).unwrap();
+        assert!(db.pager.wal_frame_count() < WAL_AUTOCHECKPOINT_FRAMES);
+    }
+
+    assert_eq!(run(&mut db, "pragma journal_mode = delete"), ExecuteResult::ExecuteSuccess);
+    assert!(!std::path::Path::new(&wal).exists());
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(db.pager.journal_mode(), JournalMode::Rollback);
+    assert_eq!(table::select_all_rows(&mut db, "users").unwrap().len(), 6_000);
+    std::fs::remove_file(path).unwrap();
+    std::fs::remove_file(copy).unwrap();
+}
+
+#[test]
+fn transactions_commit_and_roll_back() {
+    for wal in [false, true] {
+        let path = temp_db(if wal { "sqlighter_tx_wal.db" } else { "sqlighter_tx.db" });
+        let mut db = Database::open(path.clone()).unwrap();
+        if wal {
+            assert_eq!(run(&mut db, "pragma journal_mode = wal"), ExecuteResult::ExecuteSuccess);
+        }
+        assert_eq!(run(&mut db, "begin"), ExecuteResult::ExecuteSuccess);
+        for id in 0..1_000 {
+            insert_row(&mut db, id).unwrap();
+        }
+        assert_eq!(run(&mut db, "commit"), ExecuteResult::ExecuteSuccess);
+
+        // pages evicted by the small cache are brought back too
+        db.pager.set_cache_capacity(4).unwrap();
+        assert_eq!(run(&mut db, "begin transaction"), ExecuteResult::ExecuteSuccess);
+        assert!(matches!(run(&mut db, "begin"), ExecuteResult::ExecuteFailure(_)));
+        assert!(matches!(run(&mut db, "vacuum"), ExecuteResult::ExecuteFailure(_)));
+        assert_eq!(run(&mut db, "delete from users where id < 500"), ExecuteResult::ExecuteSuccess);
+        for id in 1_000..3_000 {
+            insert_row(&mut db, id).unwrap();
+        }
+        assert_eq!(run(&mut db, "update users set username = 'dave' where id = 700"), ExecuteResult::ExecuteSuccess);
+        assert_eq!(table::select_all_rows(&mut db, "users").unwrap().len(), 2_500);
+        assert_eq!(run(&mut db, "rollback"), ExecuteResult::ExecuteSuccess);
+
+        let mut keys = Vec::new();
+        collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+        assert_eq!(keys, (0..1_000).collect::<Vec<u32>>());
+        let row = table::find_row(&mut db, "users", 700).unwrap().unwrap();
+        assert_eq!(row.fields[0], text("user1"));
+        assert_eq!(db.pager.free_page_count(), 0);
+        assert!(matches!(run(&mut db, "rollback"), ExecuteResult::ExecuteFailure(_)));
+        assert!(matches!(run(&mut db, "commit"), ExecuteResult::ExecuteFailure(_)));
+
+        // bare statements commit on their own, a failing one changes nothing
+        assert_eq!(run(&mut db, "delete from users where id >= 900"), ExecuteResult::ExecuteSuccess);
+        assert!(matches!(run(&mut db, "update users set id = 5 where id = 6"), ExecuteResult::ExecuteFailure(_)));
+
+        // closing with an open transaction discards it
+        assert_eq!(run(&mut db, "begin"), ExecuteResult::ExecuteSuccess);
+        assert_eq!(run(&mut db, "delete from users"), ExecuteResult::ExecuteSuccess);
+        db.close();
+        let mut db = Database::open(path.clone()).unwrap();
+        let mut keys = Vec::new();
+        collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+        assert_eq!(keys, (0..900).collect::<Vec<u32>>());
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn savepoints_roll_back_part_of_a_transaction() {
+    let path = temp_db("sqlighter_savepoint.db");
+    let mut db = Database::open(path.clone()).unwrap();
+    db.pager.set_cache_capacity(4).unwrap();
+    let keys = |db: &mut Database| {
+        let mut keys = Vec::new();
+        collect_keys(db, ROOT_PAGE_NUM, &mut keys);
+        keys
+    };
+    for id in 0..500 {
+        insert_row(&mut db, id).unwrap();
+    }
+
+    // a savepoint outside of a transaction starts one
+    assert_eq!(run(&mut db, "savepoint batch1"), ExecuteResult::ExecuteSuccess);
+    assert!(db.pager.in_transaction());
+    for id in 500..1_000 {
+        insert_row(&mut db, id).unwrap();
+    }
+    assert_eq!(run(&mut db, "savepoint batch2"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "delete from users where id < 300"), ExecuteResult::ExecuteSuccess);
+    for id in 1_000..2_000 {
+        insert_row(&mut db, id).unwrap();
+    }
+    assert_eq!(run(&mut db, "rollback to batch2"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(keys(&mut db), (0..1_000).collect::<Vec<u32>>());
+
+    // rolled back to, batch2 is still there
+    for id in 1_000..1_100 {
+        insert_row(&mut db, id).unwrap();
+    }
+    assert_eq!(run(&mut db, "rollback to savepoint batch2"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(keys(&mut db), (0..1_000).collect::<Vec<u32>>());
+
+    // released savepoints keep their changes until an enclosing one is rolled back
+    assert_eq!(run(&mut db, "savepoint batch3"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "delete from users where id >= 900"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "release batch3"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(keys(&mut db), (0..900).collect::<Vec<u32>>());
+    assert!(matches!(run(&mut db, "rollback to batch3"), ExecuteResult::ExecuteFailure(_)));
+    assert_eq!(run(&mut db, "rollback to batch1"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(keys(&mut db), (0..500).collect::<Vec<u32>>());
+    assert!(matches!(run(&mut db, "release batch2"), ExecuteResult::ExecuteFailure(_)));
+
+    // releasing the savepoint that started the transaction commits it
+    for id in 500..600 {
+        insert_row(&mut db, id).unwrap();
+    }
+    assert_eq!(run(&mut db, "release savepoint batch1"), ExecuteResult::ExecuteSuccess);
+    assert!(!db.pager.in_transaction());
+    drop(db);
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(keys(&mut db), (0..600).collect::<Vec<u32>>());
+
+    // inside an explicit transaction, releasing every savepoint does not commit
+    assert_eq!(run(&mut db, "begin"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "savepoint a"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "delete from users where id < 100"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "release a"), ExecuteResult::ExecuteSuccess);
+    assert!(db.pager.in_transaction());
+    assert_eq!(run(&mut db, "rollback"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(keys(&mut db), (0..600).collect::<Vec<u32>>());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn memory_database_is_never_written_to_disk() {
+    let mut db = Database::open(":memory:".to_string()).unwrap();
+    // pages evicted before the rollback are put back from the journal, in memory as well
+    db.pager.set_cache_capacity(8).unwrap();
+    for id in 0..3_000 {
+        insert_row(&mut db, id).unwrap();
+    }
+    assert_eq!(run(&mut db, "begin"), ExecuteResult::ExecuteSuccess);
+    table::delete_rows(&mut db, "users", (Bound::Included(1_000), Bound::Unbounded)).unwrap();
+    assert_eq!(run(&mut db, "rollback"), ExecuteResult::ExecuteSuccess);
+    db.vacuum().unwrap();
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, (0..3_000).collect::<Vec<u32>>());
+    db.close();
+    assert!(!std::path::Path::new(":memory:").exists());
+
+    // every open is a new, empty database
+    let mut db = Database::open(":memory:".to_string()).unwrap();
+    assert!(table::select_all_rows(&mut db, "users").unwrap().is_empty());
+}
+
+#[test]
+fn mmap_storage_reads_the_same_database() {
+    let path = temp_db("sqlighter_mmap.db");
+    let mut db = Database::open_with_vfs(path.clone(), Rc::new(MmapVfs)).unwrap();
+    // evicted pages are read back through the map
+    db.pager.set_cache_capacity(8).unwrap();
+    for id in 0..5_000 {
+        let mut row = Row::new();
+        row.id = id;
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    for id in 0..2_500 {
+        table::delete_row(&mut db, "users", id * 2).unwrap();
+    }
+    // the file shrinks and grows again under the map
+    db.vacuum().unwrap();
+    for id in 5_000..6_000 {
+        let mut row = Row::new();
+        row.id = id;
+        table::insert_row(&mut db, "users", row).unwrap();
+    }
+    db.close();
+    let expected = (0..2_500).map(|id| id * 2 + 1).chain(5_000..6_000).collect::<Vec<u32>>();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    let mut keys = Vec::new();
+    collect_keys(&mut db, ROOT_PAGE_NUM, &mut keys);
+    assert_eq!(keys, expected);
+    drop(db);
+
+    let mut db = Database::open_with_vfs(path.clone(), Rc::new(MmapVfs)).unwrap();
+    db.pager.set_cache_capacity(4).unwrap();
+    let rows = table::select_all_rows(&mut db, "users").unwrap();
+    assert_eq!(rows.iter().map(|r| r.id).collect::<Vec<u32>>(), expected);
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,195 @@
+use sqlite_rs::btree::{get_node, Cell, InternalCell, Node, NodeType};
+use sqlite_rs::constants::{DEFAULT_TABLE_SQL, LEAF_NODE_HEADER_SIZE, LEAF_NODE_MAX_LOCAL, INTERNAL_NODE_HEADER_SIZE, PAGE_SIZE};
+use sqlite_rs::record::{decode_record, encode_record, get_varint, put_varint, Value};
+use sqlite_rs::table::Database;
+use sqlite_rs::utils::{read_u32, write_u32, Page};
+
+fn cell(id: u32, username: &str, email: &str) -> Cell {
+    let record = encode_record(&[Value::Text(username.to_string()), Value::Text(email.to_string())]);
+    Cell { key: id, record_size: record.len() as u32, record, overflow_page: 0 }
+}
+
+#[test]
+fn integers_are_little_endian() {
+    let mut page: Page = [0; PAGE_SIZE as usize];
+    write_u32(&mut page, 10, 0x0A0B0C0D);
+    assert_eq!(page[10..14], [0x0D, 0x0C, 0x0B, 0x0A]);
+    assert_eq!(read_u32(&page, 10), 0x0A0B0C0D);
+    assert!(page[..10].iter().chain(&page[14..]).all(|b| *b == 0));
+}
+
+#[test]
+fn varint_bytes() {
+    for (value, bytes) in [(0, vec![0]), (127, vec![0x7f]), (128, vec![0x81, 0]), (300, vec![0x82, 0x2c]), (u64::MAX, vec![0xff; 9])] {
+        let mut out = Vec::new();
+        put_varint(&mut out, value);
+        assert_eq!(out, bytes);
+        let mut offset = 0;
+        assert_eq!(get_varint(&out, &mut offset).unwrap(), value);
+        assert_eq!(offset, bytes.len());
+    }
+}
+
+#[test]
+fn record_bytes() {
+    let values = vec![
+        Value::Null,
+        Value::Integer(0),
+        Value::Integer(1),
+        Value::Integer(-2),
+        Value::Integer(0x010203040506),
+        Value::Real(1.5),
+        Value::Text("hi".to_string()),
+        Value::Blob(vec![0xAB]),
+    ];
+    let bytes = encode_record(&values);
+    let mut expected = vec![9, 0, 8, 9, 1, 5, 7, 17, 14]; // header size, then a serial type per value
+    expected.push(0xFE);
+    expected.extend_from_slice(&[6, 5, 4, 3, 2, 1]);
+    expected.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0xF8, 0x3F]);
+    expected.extend_from_slice(b"hi");
+    expected.push(0xAB);
+    assert_eq!(bytes, expected);
+    assert_eq!(decode_record(&bytes).unwrap(), values);
+    assert!(decode_record(&bytes[..bytes.len() - 1]).is_err());
+}
+
+#[test]
+fn leaf_page_bytes() {
+    let node = Node {
+        node_type: NodeType::NodeLeaf {
+            cells: vec![cell(1, "a", "x"), cell(258, "b", "y")],
+            next_leaf: 0x01020304,
+        },
+        parent: 7,
+        content_len: 2,
+    };
+    let page = node.to_page();
+
+    let mut expected = [0u8; PAGE_SIZE as usize];
+    expected[0] = 1;
+    expected[1..5].copy_from_slice(&[7, 0, 0, 0]);
+    expected[5..9].copy_from_slice(&[2, 0, 0, 0]);
+    expected[9..13].copy_from_slice(&[4, 3, 2, 1]);
+    // cell pointers follow the header, the cells are packed at the end of the page
+    expected[13..17].copy_from_slice(&[0xF6, 0x0F, 0xEC, 0x0F]);
+    expected[4086..4096].copy_from_slice(&[1, 0, 0, 0, 5, 3, 15, 15, b'a', b'x']);
+    expected[4076..4086].copy_from_slice(&[2, 1, 0, 0, 5, 3, 15, 15, b'b', b'y']);
+    assert_eq!(page, expected);
+
+    let decoded = get_node(page).unwrap();
+    assert_eq!(decoded.parent, 7);
+    assert_eq!(decoded.content_len, 2);
+    match &decoded.node_type {
+        NodeType::NodeLeaf { cells, next_leaf } => {
+            assert_eq!(*next_leaf, 0x01020304);
+            assert_eq!(cells.iter().map(|c| c.key).collect::<Vec<u32>>(), vec![1, 258]);
+        },
+        _ => panic!("Decoded a leaf as another node type"),
+    }
+    assert_eq!(decoded.to_page(), expected);
+}
+
+#[test]
+fn overflow_page_bytes() {
+    // a long record keeps its start in the leaf and names the overflow page holding the rest
+    let record = vec![7u8; LEAF_NODE_MAX_LOCAL as usize];
+    let node = Node {
+        node_type: NodeType::NodeLeaf {
+            cells: vec![Cell { key: 1, record_size: 0x01000000, record: record.clone(), overflow_page: 0x0506 }],
+            next_leaf: 0,
+        },
+        parent: 0,
+        content_len: 1,
+    };
+    let page = node.to_page();
+    let cell = PAGE_SIZE as usize - (4 + 4 + LEAF_NODE_MAX_LOCAL as usize + 4);
+    assert_eq!(page[13..15], (cell as u16).to_le_bytes());
+    assert_eq!(page[cell..cell + 8], [1, 0, 0, 0, 0x88, 0x80, 0x80, 0]);
+    assert_eq!(page[cell + 8..PAGE_SIZE as usize - 4], record[..]);
+    assert_eq!(page[PAGE_SIZE as usize - 4..], [6, 5, 0, 0]);
+    match get_node(page).unwrap().node_type {
+        NodeType::NodeLeaf { cells, .. } => assert_eq!(cells, vec![Cell { key: 1, record_size: 0x01000000, record, overflow_page: 0x0506 }]),
+        _ => panic!("Decoded a leaf as another node type"),
+    }
+
+    let node = Node {
+        node_type: NodeType::NodeOverflow { data: b"rest".to_vec(), next_page: 9 },
+        parent: 0,
+        content_len: 4,
+    };
+    let page = node.to_page();
+    let mut expected = [0u8; PAGE_SIZE as usize];
+    expected[..13].copy_from_slice(&[2, 9, 0, 0, 0, 4, 0, 0, 0, b'r', b'e', b's', b't']);
+    assert_eq!(page, expected);
+    let decoded = get_node(page).unwrap();
+    assert_eq!(decoded.content_len, 4);
+    assert!(matches!(decoded.node_type, NodeType::NodeOverflow { ref data, next_page: 9 } if data == b"rest"));
+}
+
+#[test]
+fn internal_page_bytes() {
+    let node = Node {
+        node_type: NodeType::NodeInternal {
+            cells: vec![InternalCell { child: 2, key: 100 }, InternalCell { child: 0x0300, key: 0x00010000 }],
+            right_child: 9,
+        },
+        parent: 0,
+        content_len: 2,
+    };
+    let page = node.to_page();
+
+    let mut expected = [0u8; PAGE_SIZE as usize];
+    expected[5..9].copy_from_slice(&[2, 0, 0, 0]);
+    expected[9..13].copy_from_slice(&[9, 0, 0, 0]);
+    let cells = INTERNAL_NODE_HEADER_SIZE as usize;
+    expected[cells..cells + 16].copy_from_slice(&[2, 0, 0, 0, 100, 0, 0, 0, 0, 3, 0, 0, 0, 0, 1, 0]);
+    assert_eq!(page, expected);
+
+    let decoded = get_node(page).unwrap();
+    match &decoded.node_type {
+        NodeType::NodeInternal { cells, right_child } => {
+            assert_eq!(*right_child, 9);
+            assert_eq!(cells, &vec![InternalCell { child: 2, key: 100 }, InternalCell { child: 0x0300, key: 0x00010000 }]);
+        },
+        _ => panic!("Decoded an internal node as another node type"),
+    }
+    assert_eq!(decoded.to_page(), expected);
+}
+
+#[test]
+fn file_header_bytes() {
+    let path = std::env::temp_dir().join("sqlighter_encoding_header.db");
+    let _ = std::fs::remove_file(&path);
+    let mut db = Database::open(path.to_str().unwrap().to_string()).unwrap();
+    db.close();
+
+    let bytes = std::fs::read(&path).unwrap();
+    assert_eq!(bytes.len(), 3 * PAGE_SIZE as usize);
+    let mut expected = vec![0u8; PAGE_SIZE as usize];
+    expected[..16].copy_from_slice(b"SQLighter db\0\0\0\0");
+    expected[16..20].copy_from_slice(&[5, 0, 0, 0]); // format version
+    expected[20..24].copy_from_slice(&[0, 0x10, 0, 0]); // page size
+    expected[24..28].copy_from_slice(&[1, 0, 0, 0]); // catalog root page
+    expected[36..40].copy_from_slice(&[1, 0, 0, 0]); // change counter
+    assert_eq!(bytes[..PAGE_SIZE as usize], expected[..]);
+    // the catalog leaf, listing the users table
+    let catalog = &bytes[PAGE_SIZE as usize..2 * PAGE_SIZE as usize];
+    assert_eq!(catalog[0], 1);
+    assert_eq!(catalog[5..9], [1, 0, 0, 0]);
+    let cell = PAGE_SIZE as usize - 4 - 1 - 84;
+    assert_eq!(catalog[LEAF_NODE_HEADER_SIZE as usize..LEAF_NODE_HEADER_SIZE as usize + 2], (cell as u16).to_le_bytes());
+    assert!(catalog[LEAF_NODE_HEADER_SIZE as usize + 2..cell].iter().all(|b| *b == 0));
+    assert_eq!(catalog[cell..cell + 4], [1, 0, 0, 0]);
+    assert_eq!(catalog[cell + 4], 84); // record size
+    // type, name, root page and sql: text of 5 bytes twice, a one-byte integer, then the sql
+    let record = cell + 5;
+    assert_eq!(catalog[record..record + 6], [6, 23, 23, 1, 0x81, 13 + 2 * DEFAULT_TABLE_SQL.len() as u8 - 128]);
+    assert_eq!(&catalog[record + 6..record + 16], b"tableusers");
+    assert_eq!(catalog[record + 16], 2); // root page
+    assert_eq!(&catalog[record + 17..], DEFAULT_TABLE_SQL.as_bytes());
+    // an empty root leaf
+    assert_eq!(bytes[2 * PAGE_SIZE as usize], 1);
+    assert!(bytes[2 * PAGE_SIZE as usize + 1..].iter().all(|b| *b == 0));
+    std::fs::remove_file(path).unwrap();
+}
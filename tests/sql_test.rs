@@ -0,0 +1,429 @@
+use std::ops::Bound;
+
+use sqlite_rs::ast::{Assignment, CompareOp, Condition, Constant, Ident, Insert, Literal, OrderBy, Select, Stmt, Update};
+use sqlite_rs::lexer::{tokenize, Keyword, Position, TokenKind};
+use sqlite_rs::parser::parse;
+use sqlite_rs::catalog::{read_catalog, EntryType};
+use sqlite_rs::constants::{CATALOG_TABLE_SQL, DEFAULT_TABLE_SQL, DEFAULT_TEXT_SIZE, ROOT_PAGE_NUM};
+use sqlite_rs::record::Value;
+use sqlite_rs::schema::{ColumnType, Schema};
+use sqlite_rs::sql::{execute_statement, prepare, prepare_for, prepare_statement, ExecuteResult, PrepareResult, Statement, StatementType};
+use sqlite_rs::table::{self, Order, Row, Database};
+
+fn at(line: u32, column: u32) -> Position {
+    Position { line, column }
+}
+
+fn ident(name: &str, line: u32, column: u32) -> Ident {
+    Ident { name: name.to_string(), position: at(line, column) }
+}
+
+fn value(literal: Literal, line: u32, column: u32) -> Constant {
+    Constant { literal, position: at(line, column) }
+}
+
+fn new_statement() -> Statement {
+    Statement { statement_type: StatementType::Select, row_to_insert: Row::new(), table: String::new() }
+}
+
+#[test]
+fn tokens() {
+    let tokens = tokenize("SeLeCt 'it''s a b', -12 3.5 x_1 a@b.c \"Order\" <= >= <> != ( ) * ;\n  = < >").unwrap();
+    let kinds = tokens.iter().map(|t| t.kind.clone()).collect::<Vec<TokenKind>>();
+    assert_eq!(kinds, vec![
+        TokenKind::Keyword(Keyword::Select),
+        TokenKind::String("it's a b".to_string()),
+        TokenKind::Comma,
+        TokenKind::Integer(-12),
+        TokenKind::Real(3.5),
+        TokenKind::Identifier("x_1".to_string()),
+        TokenKind::Identifier("a@b.c".to_string()),
+        TokenKind::Identifier("Order".to_string()),
+        TokenKind::LessOrEqual,
+        TokenKind::GreaterOrEqual,
+        TokenKind::NotEquals,
+        TokenKind::NotEquals,
+        TokenKind::LeftParen,
+        TokenKind::RightParen,
+        TokenKind::Star,
+        TokenKind::Semicolon,
+        TokenKind::Equals,
+        TokenKind::Less,
+        TokenKind::Greater,
+        TokenKind::End,
+    ]);
+    assert_eq!(tokens[1].text, "'it''s a b'");
+    assert_eq!(tokens[3].position, at(1, 21));
+    assert_eq!(tokens[16].position, at(2, 3));
+    assert_eq!(tokens[19].position, at(2, 8));
+
+    let err = tokenize("select 'open").unwrap_err();
+    assert_eq!((err.result, err.position), (PrepareResult::PrepareSyntaxError, at(1, 8)));
+    let err = tokenize("select # from").unwrap_err();
+    assert_eq!(err.position, at(1, 8));
+    assert!(tokenize("insert 99999999999999999999 a b").is_err());
+}
+
+#[test]
+fn statements_parse_into_an_ast() {
+    assert_eq!(parse("INSERT INTO users (id, email) VALUES (3, 'John Smith');").unwrap(), Stmt::Insert(Insert {
+        table: ident("users", 1, 13),
+        columns: vec![ident("id", 1, 20), ident("email", 1, 24)],
+        values: vec![value(Literal::Integer(3), 1, 39), value(Literal::Text("John Smith".to_string()), 1, 42)],
+    }));
+    assert_eq!(parse("insert 1 bob bob@mail.com").unwrap(), Stmt::Insert(Insert {
+        table: ident("users", 1, 1),
+        columns: Vec::new(),
+        values: vec![
+            value(Literal::Integer(1), 1, 8),
+            value(Literal::Text("bob".to_string()), 1, 10),
+            value(Literal::Text("bob@mail.com".to_string()), 1, 14),
+        ],
+    }));
+    assert_eq!(parse("select *\nfrom users\nwhere id > 2 and id <= 9\norder by id desc limit 4").unwrap(), Stmt::Select(Select {
+        table: Some(ident("users", 2, 6)),
+        filter: vec![
+            Condition { column: ident("id", 3, 7), op: CompareOp::Greater, value: value(Literal::Integer(2), 3, 12) },
+            Condition { column: ident("id", 3, 18), op: CompareOp::LessOrEqual, value: value(Literal::Integer(9), 3, 24) },
+        ],
+        order_by: Some(OrderBy { column: ident("id", 4, 10), order: Order::Descending }),
+        limit: Some(value(Literal::Integer(4), 4, 24)),
+    }));
+    assert_eq!(parse("Update users SET username = 'a b' WHERE id = 1").unwrap(), Stmt::Update(Update {
+        table: ident("users", 1, 8),
+        assignments: vec![Assignment { column: ident("username", 1, 18), value: value(Literal::Text("a b".to_string()), 1, 29) }],
+        filter: vec![Condition { column: ident("id", 1, 41), op: CompareOp::Equals, value: value(Literal::Integer(1), 1, 46) }],
+    }));
+    assert_eq!(parse("ROLLBACK TO SAVEPOINT s1").unwrap(), Stmt::RollbackTo(ident("s1", 1, 23)));
+}
+
+#[test]
+fn errors_point_at_the_offending_token() {
+    let err = parse("select * form users").unwrap_err();
+    assert_eq!((err.result, err.position), (PrepareResult::PrepareSyntaxError, at(1, 10)));
+    assert_eq!(err.to_string(), "expected 'from', found 'form' at line 1, column 10");
+
+    let err = parse("select * from users\nwhere id = 1 or id = 2").unwrap_err();
+    assert_eq!(err.position, at(2, 14));
+    let err = parse("select * from users where").unwrap_err();
+    assert_eq!(err.to_string(), "expected a column name, found end of input at line 1, column 26");
+    let err = parse("select * from users; select").unwrap_err();
+    assert_eq!(err.position, at(1, 22));
+    let err = parse("drop table users").unwrap_err();
+    assert_eq!((err.result, err.position), (PrepareResult::PrepareUnrecognizedStatement, at(1, 1)));
+
+    let mut statement = new_statement();
+    let err = prepare("insert into users values (1, 'x', 'y', 'z')", &mut statement).unwrap_err();
+    assert_eq!((err.result, err.position), (PrepareResult::PrepareSyntaxError, at(1, 13)));
+    let err = prepare("delete from users where id = -4", &mut statement).unwrap_err();
+    assert_eq!((err.result, err.position), (PrepareResult::PrepareNegativeID, at(1, 30)));
+    let err = prepare(&format!("update users set email = '{}'", "e".repeat(256)), &mut statement).unwrap_err();
+    assert_eq!((err.result, err.position), (PrepareResult::PrepareStringTooLong, at(1, 26)));
+    let err = prepare("select * from users where name = 'bob'", &mut statement).unwrap_err();
+    assert_eq!(err.to_string(), "no such column: name at line 1, column 27");
+    let err = prepare("select * from users where username = 'x'", &mut statement).unwrap_err();
+    assert_eq!(err.to_string(), "only the key column id can be used in a where clause, not username at line 1, column 27");
+    let err = prepare("delete from users where id > 3 and email = 'x'", &mut statement).unwrap_err();
+    assert_eq!(err.to_string(), "only the key column id can be used in a where clause, not email at line 1, column 36");
+    let err = prepare("select * from users order by email desc", &mut statement).unwrap_err();
+    assert_eq!(err.to_string(), "only the key column id can be used in order by, not email at line 1, column 30");
+    let err = prepare("select * from users order by age", &mut statement).unwrap_err();
+    assert_eq!(err.to_string(), "no such column: age at line 1, column 30");
+}
+
+#[test]
+fn keywords_are_case_insensitive_and_strings_keep_spaces() {
+    let mut db = Database::open(":memory:".to_string()).unwrap();
+    for cmd in ["INSERT INTO users VALUES (1, 'Ada Lovelace', 'ada@example.com')", "Insert 2 bob bob@example.com;"] {
+        let mut statement = new_statement();
+        assert_eq!(prepare_statement(cmd, &mut statement), PrepareResult::PrepareSuccess);
+        assert_eq!(execute_statement(statement, &mut db), ExecuteResult::ExecuteSuccess);
+    }
+    let mut statement = new_statement();
+    assert_eq!(prepare_statement("insert into users (email, id) values ('x@y.z', 3)", &mut statement), PrepareResult::PrepareSuccess);
+    assert_eq!(execute_statement(statement, &mut db), ExecuteResult::ExecuteSuccess);
+
+    let row = table::find_row(&mut db, "users", 1).unwrap().unwrap();
+    assert_eq!(row.fields[0], Value::Text("Ada Lovelace".to_string()));
+    let row = table::find_row(&mut db, "users", 3).unwrap().unwrap();
+    assert_eq!(row.fields, vec![Value::Null, Value::Text("x@y.z".to_string())]);
+
+    let mut statement = new_statement();
+    assert_eq!(prepare_statement("SELECT * FROM users WHERE ID >= 2", &mut statement), PrepareResult::PrepareSyntaxError);
+    assert_eq!(prepare_statement("SELECT * FROM users WHERE id >= 2 ORDER BY id DESC", &mut statement), PrepareResult::PrepareSuccess);
+    assert_eq!(execute_statement(statement, &mut db), ExecuteResult::ExecuteSuccess);
+}
+
+fn run(db: &mut Database, cmd: &str) -> ExecuteResult {
+    let mut statement = new_statement();
+    prepare_for(&db.tables, cmd, &mut statement).unwrap();
+    execute_statement(statement, db)
+}
+
+#[test]
+fn short_insert_takes_its_values_as_written() {
+    assert_eq!(parse("insert 1 a+b x@y;").unwrap(), Stmt::Insert(Insert {
+        table: ident("users", 1, 1),
+        columns: Vec::new(),
+        values: vec![
+            value(Literal::Integer(1), 1, 8),
+            value(Literal::Text("a+b".to_string()), 1, 10),
+            value(Literal::Text("x@y".to_string()), 1, 14),
+        ],
+    }));
+
+    let mut db = Database::open(":memory:".to_string()).unwrap();
+    let rows = [(3, "user-3", "a@b.c"), (4, "josé", "a@b.c"), (5, "a+b", "x@y"), (6, "null", "a@b.c"), (7, "order", "'quoted'")];
+    for (id, username, email) in rows {
+        assert_eq!(run(&mut db, &format!("insert {} {} {}", id, username, email)), ExecuteResult::ExecuteSuccess);
+    }
+    let stored = table::select_all_rows(&mut db, "users").unwrap();
+    for (row, (id, username, email)) in stored.iter().zip(rows) {
+        assert_eq!(row.id, id);
+        assert_eq!(row.fields, vec![Value::Text(username.to_string()), Value::Text(email.to_string())]);
+    }
+
+    let mut statement = new_statement();
+    assert_eq!(prepare_statement("insert 8 a b c", &mut statement), PrepareResult::PrepareSyntaxError);
+    assert_eq!(prepare_statement("insert x a b", &mut statement), PrepareResult::PrepareSyntaxError);
+}
+
+#[test]
+fn create_table_defines_the_columns_rows_are_stored_with() {
+    let path = std::env::temp_dir().join("sqlighter_create_table.db");
+    let path = path.to_str().unwrap().to_string();
+    let _ = std::fs::remove_file(&path);
+    let sql = "CREATE TABLE orders (id integer, item varchar(20), qty int, note text)";
+
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(db.tables.len(), 1);
+    assert_eq!(db.tables[0].schema, Schema::users());
+    assert_eq!(run(&mut db, &format!("{};", sql)), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "insert into orders values (1, 'green apple', 3, 'x')"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "insert into orders (qty, id) values (-5, 2)"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "update orders set qty = 4 where id = 1"), ExecuteResult::ExecuteSuccess);
+    db.close();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    let orders = db.table("orders").unwrap();
+    assert_eq!(orders.schema.sql, sql);
+    assert_eq!(orders.schema.columns.iter().map(|c| c.column_type).collect::<Vec<ColumnType>>(),
+        vec![ColumnType::Integer, ColumnType::Text(20), ColumnType::Integer, ColumnType::Text(DEFAULT_TEXT_SIZE)]);
+    let rows = table::select_all_rows(&mut db, "orders").unwrap();
+    assert_eq!(rows, vec![
+        Row { id: 1, fields: vec![Value::Text("green apple".to_string()), Value::Integer(4), Value::Text("x".to_string())] },
+        Row { id: 2, fields: vec![Value::Null, Value::Integer(-5), Value::Null] },
+    ]);
+
+    let mut statement = new_statement();
+    let err = prepare_for(&db.tables, "insert into items values (3)", &mut statement).unwrap_err();
+    assert_eq!(err.to_string(), "no such table: items at line 1, column 13");
+    let err = prepare_for(&db.tables, "update orders set qty = 'many' where id = 1", &mut statement).unwrap_err();
+    assert_eq!(err.to_string(), "qty must be an integer at line 1, column 25");
+    let err = prepare_for(&db.tables, "insert into orders values (3, 'a', 1, 'b', 'c')", &mut statement).unwrap_err();
+    assert_eq!(err.to_string(), "5 values for 4 columns at line 1, column 13");
+    let err = prepare_for(&db.tables, &format!("insert into orders (id, item) values (3, '{}')", "a".repeat(21)), &mut statement).unwrap_err();
+    assert_eq!(err.result, PrepareResult::PrepareStringTooLong);
+
+    assert_eq!(run(&mut db, sql), ExecuteResult::ExecuteFailure("Table orders already exists".to_string()));
+    assert_eq!(run(&mut db, "create table sqlighter_schema (id integer)"),
+        ExecuteResult::ExecuteFailure("Table name sqlighter_schema is reserved for the system catalog".to_string()));
+
+    // a rollback undoes the table, its root page and its catalog entry
+    let entries = read_catalog(&mut db.pager).unwrap();
+    assert_eq!(run(&mut db, "begin"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "create table items (id integer)"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "insert into items values (1)"), ExecuteResult::ExecuteSuccess);
+    let root_page_num = db.table("items").unwrap().root_page_num;
+    assert_eq!(run(&mut db, "rollback"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(db.table("items").unwrap_err().to_string(), "No such table: items");
+    assert_eq!(read_catalog(&mut db.pager).unwrap(), entries);
+    assert_eq!(run(&mut db, "begin"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "savepoint s"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "create table items (id integer)"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "rollback to s"), ExecuteResult::ExecuteSuccess);
+    assert!(db.table("items").is_err());
+    assert_eq!(run(&mut db, "create table items (id integer, name text(8))"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "commit"), ExecuteResult::ExecuteSuccess);
+    db.close();
+
+    // the page the rolled back table took was not leaked
+    let db = Database::open(path.clone()).unwrap();
+    assert_eq!(db.table("items").unwrap().schema.columns.len(), 2);
+    assert_eq!(db.table("items").unwrap().root_page_num, root_page_num);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn statements_target_the_table_they_name() {
+    let mut db = Database::open(":memory:".to_string()).unwrap();
+    assert_eq!(run(&mut db, "create table customers (id integer, name text(20))"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "create table orders (id integer, customer integer, item text(20))"), ExecuteResult::ExecuteSuccess);
+    let roots = db.tables.iter().map(|t| t.root_page_num).collect::<Vec<u32>>();
+    assert_eq!(roots, vec![ROOT_PAGE_NUM, ROOT_PAGE_NUM + 1, ROOT_PAGE_NUM + 2]);
+
+    // enough rows for every tree to split, written in turns
+    for id in 1..=300 {
+        assert_eq!(run(&mut db, &format!("insert into customers values ({}, 'c{}')", id, id)), ExecuteResult::ExecuteSuccess);
+        assert_eq!(run(&mut db, &format!("insert into orders values ({}, {}, 'o{}')", id * 2, id, id)), ExecuteResult::ExecuteSuccess);
+        assert_eq!(run(&mut db, &format!("insert {} u{} u{}@mail.com", id, id, id)), ExecuteResult::ExecuteSuccess);
+    }
+    assert_eq!(run(&mut db, "delete from orders where id > 100"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "update customers set name = 'first' where id = 1"), ExecuteResult::ExecuteSuccess);
+
+    assert_eq!(table::select_all_rows(&mut db, "customers").unwrap().len(), 300);
+    assert_eq!(table::select_all_rows(&mut db, "users").unwrap().len(), 300);
+    let orders = table::select_all_rows(&mut db, "orders").unwrap();
+    assert_eq!(orders.iter().map(|r| r.id).collect::<Vec<u32>>(), (1..=50).map(|id| id * 2).collect::<Vec<u32>>());
+    assert_eq!(orders[0].fields, vec![Value::Integer(1), Value::Text("o1".to_string())]);
+    let first = table::find_row(&mut db, "customers", 1).unwrap().unwrap();
+    assert_eq!(first.fields, vec![Value::Text("first".to_string())]);
+    assert_eq!(table::find_row(&mut db, "users", 1).unwrap().unwrap().fields[0], Value::Text("u1".to_string()));
+
+    db.vacuum().unwrap();
+    assert_eq!(db.tables.iter().map(|t| t.schema.name.as_str()).collect::<Vec<&str>>(), vec!["users", "customers", "orders"]);
+    assert_eq!(table::select_rows(&mut db, "orders", (Bound::Unbounded, Bound::Unbounded), Order::Descending, Some(1)).unwrap()[0].id, 100);
+    assert_eq!(table::select_all_rows(&mut db, "customers").unwrap().len(), 300);
+    assert_eq!(table::select_all_rows(&mut db, "nothing").unwrap_err().to_string(), "No such table: nothing");
+}
+
+#[test]
+fn schemas_are_checked_when_created() {
+    assert_eq!(Schema::from_sql(DEFAULT_TABLE_SQL).unwrap(), Schema::users());
+
+    let error = |sql: &str| Schema::from_sql(sql).unwrap_err().to_string();
+    assert_eq!(error("create table t (id integer, price money)"), "unknown column type: money at line 1, column 35");
+    assert_eq!(error("create table t (name text, id integer)"), "the first column is the key and must be an integer at line 1, column 22");
+    assert_eq!(error("create table t (id integer, a text(4), a int)"), "duplicate column name: a at line 1, column 40");
+    assert_eq!(error("create table t (id integer(4))"), "integer columns take no size at line 1, column 28");
+    assert_eq!(error("create table t (id integer, a text(0))"), "text size must be between 1 and 1000000000 at line 1, column 36");
+    assert_eq!(error("create table t (id integer, a blob(1000000001))"), "blob size must be between 1 and 1000000000 at line 1, column 36");
+    assert_eq!(Schema::from_sql("create table t (id int, a text, b text)").unwrap().columns.len(), 3);
+    assert_eq!(error("create table t ()"), "expected a column name, found ')' at line 1, column 17");
+    assert_eq!(error("select * from t"), "expected a create table statement at line 1, column 1");
+}
+
+#[test]
+fn the_catalog_records_each_table_its_root_and_definition() {
+    let path = std::env::temp_dir().join("sqlighter_catalog.db");
+    let path = path.to_str().unwrap().to_string();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(Schema::from_sql(CATALOG_TABLE_SQL).unwrap(), Schema::catalog());
+
+    let mut db = Database::open(path.clone()).unwrap();
+    let entries = read_catalog(&mut db.pager).unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!((entries[0].entry_type, entries[0].name.as_str()), (EntryType::Table, "users"));
+    assert_eq!((entries[0].root_page_num, entries[0].sql.as_str()), (ROOT_PAGE_NUM, DEFAULT_TABLE_SQL));
+
+    let columns = |n: u32| (0..n).map(|i| format!("c{} int", i)).collect::<Vec<String>>().join(", ");
+    let too_long = format!("create table t (id integer, {})", columns(8_000));
+    assert_eq!(run(&mut db, &too_long), ExecuteResult::ExecuteFailure("Definition of t is longer than 65536 bytes".to_string()));
+    assert_eq!(read_catalog(&mut db.pager).unwrap(), entries);
+    let wide = format!("create table wide (id integer, {})", columns(500));
+    assert_eq!(run(&mut db, &wide), ExecuteResult::ExecuteSuccess);
+
+    let sql = "create table notes (id int, body text(100))";
+    assert_eq!(run(&mut db, sql), ExecuteResult::ExecuteSuccess);
+    for id in 1..=200 {
+        assert_eq!(run(&mut db, &format!("insert into notes values ({}, 'note')", id)), ExecuteResult::ExecuteSuccess);
+    }
+    assert_eq!(run(&mut db, "delete from notes where id > 10"), ExecuteResult::ExecuteSuccess);
+    db.vacuum().unwrap();
+    db.close();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    let entries = read_catalog(&mut db.pager).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert_eq!((entries[1].name.as_str(), entries[1].sql.as_str()), ("wide", wide.as_str()));
+    assert_eq!(db.table("wide").unwrap().schema.columns.len(), 501);
+    assert_eq!((entries[2].name.as_str(), entries[2].sql.as_str()), ("notes", sql));
+    assert_eq!(entries[2].root_page_num, db.table("notes").unwrap().root_page_num);
+    assert_eq!(table::select_all_rows(&mut db, "notes").unwrap().len(), 10);
+    db.close();
+
+    // files from before the catalog keep their table definition elsewhere
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[16..20].copy_from_slice(&[2, 0, 0, 0]);
+    std::fs::write(&path, bytes).unwrap();
+    assert_eq!(Database::open(path.clone()).err().unwrap().to_string(),
+        "Database file format version 2 is older than the oldest supported version 5");
+    // and files from before variable-length cells lay their leaves out differently
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[16..20].copy_from_slice(&[4, 0, 0, 0]);
+    std::fs::write(&path, bytes).unwrap();
+    assert_eq!(Database::open(path.clone()).err().unwrap().to_string(),
+        "Database file format version 4 is older than the oldest supported version 5");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn columns_hold_integers_reals_text_blobs_and_nulls() {
+    let mut db = Database::open(":memory:".to_string()).unwrap();
+    assert_eq!(run(&mut db, "create table items (id integer, qty integer, price real, name text(8), tag blob(4))"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "insert into items values (1, -300000, 2.5, 'pen', x'0aFF')"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "insert into items values (2, 9000000000, 3, null, 'ab')"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "insert into items (id) values (3)"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "update items set price = null, name = 7 where id = 1"), ExecuteResult::ExecuteSuccess);
+
+    let rows = table::select_all_rows(&mut db, "items").unwrap();
+    assert_eq!(rows[0].fields, vec![Value::Integer(-300000), Value::Null, Value::Text("7".to_string()), Value::Blob(vec![0x0A, 0xFF])]);
+    assert_eq!(rows[1].fields, vec![Value::Integer(9000000000), Value::Real(3.0), Value::Null, Value::Blob(b"ab".to_vec())]);
+    assert_eq!(rows[2].fields, vec![Value::Null; 4]);
+    assert_eq!(rows[1].fields.iter().map(|v| v.to_string()).collect::<Vec<String>>(), vec!["9000000000", "3.0", "NULL", "X'6162'"]);
+
+    let mut statement = new_statement();
+    let err = prepare_for(&db.tables, "insert into items (id, qty) values (4, 1.5)", &mut statement).unwrap_err();
+    assert_eq!(err.to_string(), "qty must be an integer at line 1, column 40");
+    let err = prepare_for(&db.tables, "update items set price = 'cheap'", &mut statement).unwrap_err();
+    assert_eq!(err.to_string(), "price must be a number at line 1, column 26");
+    let err = prepare_for(&db.tables, "update items set tag = x'0102030405'", &mut statement).unwrap_err();
+    assert_eq!((err.result, err.to_string()), (PrepareResult::PrepareStringTooLong, "blob is longer than 4 bytes at line 1, column 24".to_string()));
+
+    // rows built without a statement are checked against the schema too
+    let row = Row { id: 4, fields: vec![Value::Integer(1), Value::Integer(2)] };
+    table::insert_row(&mut db, "items", row).unwrap();
+    assert_eq!(table::find_row(&mut db, "items", 4).unwrap().unwrap().fields, vec![Value::Integer(1), Value::Real(2.0), Value::Null, Value::Null]);
+    let row = Row { id: 5, fields: vec![Value::Text("x".to_string())] };
+    assert_eq!(table::insert_row(&mut db, "items", row).unwrap_err().to_string(), "Wrong type of value for qty: x");
+}
+
+#[test]
+fn rows_larger_than_a_page_spill_to_overflow_pages() {
+    let path = std::env::temp_dir().join("sqlighter_overflow.db");
+    let path = path.to_str().unwrap().to_string();
+    let _ = std::fs::remove_file(&path);
+    let long = (0..50_000).map(|i| char::from(b'a' + (i % 26) as u8)).collect::<String>();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    assert_eq!(run(&mut db, "create table t (id int, a text, b text(100000))"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(run(&mut db, "insert into t values (1, 'short', 'row')"), ExecuteResult::ExecuteSuccess);
+    let row = Row { id: 2, fields: vec![Value::Text("a".repeat(200)), Value::Text("b".repeat(200))] };
+    table::insert_row(&mut db, "t", row).unwrap();
+    let row = Row { id: 3, fields: vec![Value::Null, Value::Text(long.clone())] };
+    table::insert_row(&mut db, "t", row).unwrap();
+    assert_eq!(table::find_row(&mut db, "t", 3).unwrap().unwrap().fields[1], Value::Text(long.clone()));
+
+    // the pages of a chain are freed with their row, and reused by the next one
+    assert!(table::delete_row(&mut db, "t", 3).unwrap());
+    let freed = db.pager.free_page_count();
+    // 49000 bytes past the part kept in the leaf, over pages of 4087 bytes
+    assert_eq!(freed, 12);
+    let row = Row { id: 3, fields: vec![Value::Null, Value::Text(long.clone())] };
+    table::insert_row(&mut db, "t", row).unwrap();
+    assert_eq!(db.pager.free_page_count(), 0);
+    assert_eq!(run(&mut db, "update t set b = 'small' where id = 3"), ExecuteResult::ExecuteSuccess);
+    assert_eq!(db.pager.free_page_count(), freed);
+    assert_eq!(run(&mut db, &format!("update t set b = '{}' where id = 1", &long[..20_000])), ExecuteResult::ExecuteSuccess);
+    db.close();
+
+    let mut db = Database::open(path.clone()).unwrap();
+    let rows = table::select_all_rows(&mut db, "t").unwrap();
+    assert_eq!(rows[0].fields, vec![Value::Text("short".to_string()), Value::Text(long[..20_000].to_string())]);
+    assert_eq!(rows[1].fields[1], Value::Text("b".repeat(200)));
+    assert_eq!(rows[2].fields, vec![Value::Null, Value::Text("small".to_string())]);
+    db.vacuum().unwrap();
+    assert_eq!(db.pager.free_page_count(), 0);
+    assert_eq!(table::select_all_rows(&mut db, "t").unwrap(), rows);
+    std::fs::remove_file(&path).unwrap();
+}